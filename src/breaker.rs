@@ -0,0 +1,258 @@
+use crate::{
+    emit_event_for, observe_api_call, Context, Error, Result, CIRCUIT_BREAKER_OPEN,
+    CIRCUIT_BREAKER_TRIPS_TOTAL, SERVICE_NAME,
+};
+use k8s_openapi::{
+    api::core::v1::{ConfigMap, ObjectReference},
+    apimachinery::pkg::apis::meta::v1::ObjectMeta,
+};
+use kube::{
+    api::{DeleteParams, Patch, PatchParams},
+    error::ErrorResponse,
+    runtime::events::EventType,
+    ResourceExt,
+};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+use tracing::{info, warn};
+
+/// ConfigMap persisting an open circuit breaker across restarts
+pub const BREAKER_CONFIGMAP_NAME: &str = "node-taint-preserver-circuit-breaker";
+const RESUME_ANNOTATION: &str = "nodetaintpreserver.example.com/resume";
+const BREAKER_SYNC_INTERVAL: Duration = Duration::from_secs(15);
+/// How often nodes waiting for a restore re-check the breaker
+pub(crate) const BREAKER_RECHECK: Duration = Duration::from_secs(30);
+
+/// Trips when too many nodes are cleaned up within a window, pausing
+/// restores until an operator explicitly resumes them
+#[derive(Default)]
+pub struct CircuitBreaker {
+    cleanups: Mutex<VecDeque<(Instant, String)>>,
+    open: AtomicBool,
+    // Times this process opened the breaker, so a sync that read the
+    // ConfigMap before a trip does not close the breaker again
+    trips: Mutex<u64>,
+}
+
+impl CircuitBreaker {
+    /// Whether restores are currently paused
+    pub fn is_open(&self) -> bool {
+        self.open.load(Ordering::SeqCst)
+    }
+
+    fn set_open(&self, open: bool) {
+        self.open.store(open, Ordering::SeqCst);
+        CIRCUIT_BREAKER_OPEN.set(open as i64);
+    }
+
+    /// Open the breaker after its ConfigMap was written, and forget the
+    /// counted cleanups
+    fn open_tripped(&self) {
+        let mut trips = self.trips.lock().unwrap();
+        *trips += 1;
+        self.set_open(true);
+        self.cleanups.lock().unwrap().clear();
+    }
+
+    fn trips(&self) -> u64 {
+        *self.trips.lock().unwrap()
+    }
+
+    /// Follow the state of the ConfigMap, unless the breaker was tripped
+    /// since it was read. Returns whether the breaker changed.
+    fn sync_open(&self, open: bool, trips_before: u64) -> bool {
+        let trips = self.trips.lock().unwrap();
+        if *trips != trips_before || open == self.is_open() {
+            return false;
+        }
+        self.set_open(open);
+        true
+    }
+
+    /// Count a node cleanup, returning the number of distinct nodes cleaned
+    /// up within the window if that exceeds the threshold
+    fn record_cleanup(
+        &self,
+        node_name: &str,
+        max_cleanups: u32,
+        window: Duration,
+    ) -> Option<usize> {
+        if max_cleanups == 0 || self.is_open() {
+            return None;
+        }
+
+        let now = Instant::now();
+        let mut cleanups = self.cleanups.lock().unwrap();
+        while cleanups
+            .front()
+            .is_some_and(|(at, _)| now.duration_since(*at) > window)
+        {
+            cleanups.pop_front();
+        }
+        // Retried cleanups of the same node only count once
+        if !cleanups.iter().any(|(_, name)| name == node_name) {
+            cleanups.push_back((now, node_name.to_string()));
+        }
+
+        // Counted cleanups are kept until the trip persisted, so a failed
+        // trip is retried on the next cleanup
+        let count = cleanups.len();
+        (count > max_cleanups as usize).then_some(count)
+    }
+}
+
+/// Reference to the breaker ConfigMap, used as the involved object of
+/// cluster-level Events
+fn breaker_reference(ctx: &Context) -> ObjectReference {
+    ObjectReference {
+        api_version: Some("v1".to_string()),
+        kind: Some("ConfigMap".to_string()),
+        name: Some(BREAKER_CONFIGMAP_NAME.to_string()),
+        namespace: Some(ctx.config().configmap_namespace.clone()),
+        ..Default::default()
+    }
+}
+
+/// Count a completed node cleanup, tripping the breaker on mass deletion.
+/// Failing to persist the breaker does not fail the cleanup itself.
+pub(crate) async fn record_cleanup(ctx: &Context, node_name: &str) {
+    let config = ctx.config();
+    let Some(count) = ctx.breaker.record_cleanup(
+        node_name,
+        config.circuit_breaker_max_cleanups,
+        config.circuit_breaker_window(),
+    ) else {
+        return;
+    };
+
+    let reason = format!(
+        "{} nodes were deleted within {}s",
+        count, config.circuit_breaker_window_seconds
+    );
+    if let Err(e) = trip(ctx, &reason).await {
+        warn!(node = %node_name, error = ?e, "Failed to persist circuit breaker state");
+    }
+}
+
+/// Persist the breaker so it stays open across restarts, then open it.
+/// The breaker stays closed when the ConfigMap cannot be written, as the
+/// next sync would not find it.
+async fn trip(ctx: &Context, reason: &str) -> Result<()> {
+    let tripped_at = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let cm = ConfigMap {
+        metadata: ObjectMeta {
+            name: Some(BREAKER_CONFIGMAP_NAME.to_string()),
+            namespace: Some(ctx.config().configmap_namespace.clone()),
+            ..Default::default()
+        },
+        data: Some(BTreeMap::from([
+            ("tripped_at".to_string(), tripped_at.to_string()),
+            ("reason".to_string(), reason.to_string()),
+        ])),
+        ..Default::default()
+    };
+    let cm_api = ctx.cm_api();
+    observe_api_call(
+        "configmaps",
+        "patch",
+        cm_api.patch(
+            BREAKER_CONFIGMAP_NAME,
+            &PatchParams::apply(SERVICE_NAME).force(),
+            &Patch::Apply(&cm),
+        ),
+    )
+    .await
+    .map_err(Error::Kube)?;
+    ctx.breaker.open_tripped();
+    CIRCUIT_BREAKER_TRIPS_TOTAL.inc();
+
+    let message = format!(
+        "Mass node deletion detected ({}), pausing taint restores until resumed",
+        reason
+    );
+    warn!("{}", message);
+    emit_event_for(
+        ctx,
+        breaker_reference(ctx),
+        "MassDeletionDetected",
+        &message,
+        EventType::Warning,
+    )
+    .await;
+    Ok(())
+}
+
+/// Close the breaker by deleting its ConfigMap, returning whether it was open
+pub async fn resume(ctx: &Context) -> Result<bool> {
+    let cm_api = ctx.cm_api();
+    match observe_api_call(
+        "configmaps",
+        "delete",
+        cm_api.delete(BREAKER_CONFIGMAP_NAME, &DeleteParams::default()),
+    )
+    .await
+    {
+        Ok(_) => Ok(true),
+        Err(kube::Error::Api(ErrorResponse { code: 404, .. })) => Ok(false),
+        Err(e) => Err(Error::Kube(e)),
+    }
+}
+
+/// Keep the in-memory breaker in sync with its ConfigMap: an existing
+/// ConfigMap opens it (e.g. after a restart), a deleted one closes it, and
+/// the resume annotation deletes the ConfigMap
+pub async fn sync_circuit_breaker(ctx: Arc<Context>) {
+    let mut interval = tokio::time::interval(BREAKER_SYNC_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(e) = sync_breaker_state(&ctx).await {
+            warn!(error = ?e, "Failed to sync circuit breaker state");
+        }
+    }
+}
+
+/// Sync the in-memory breaker with its ConfigMap once. A breaker this
+/// process opened is only closed once its ConfigMap is deleted or carries
+/// the resume annotation; a read racing with the trip is ignored.
+pub async fn sync_breaker_state(ctx: &Context) -> Result<()> {
+    let trips_before = ctx.breaker.trips();
+    let cm_api = ctx.cm_api();
+    let cm = observe_api_call("configmaps", "get", cm_api.get_opt(BREAKER_CONFIGMAP_NAME)).await?;
+
+    let resume_requested = cm.as_ref().is_some_and(|cm| {
+        cm.annotations()
+            .get(RESUME_ANNOTATION)
+            .is_some_and(|v| v == "true")
+    });
+    if resume_requested {
+        resume(ctx).await?;
+    }
+
+    let open = cm.is_some() && !resume_requested;
+    if !ctx.breaker.sync_open(open, trips_before) {
+        return Ok(());
+    }
+    if open {
+        warn!("Circuit breaker is open, taint restores are paused");
+    } else {
+        info!("Circuit breaker resumed, taint restores continue");
+        emit_event_for(
+            ctx,
+            breaker_reference(ctx),
+            "RestoresResumed",
+            "Circuit breaker resumed, taint restores continue",
+            EventType::Normal,
+        )
+        .await;
+    }
+    Ok(())
+}
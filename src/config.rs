@@ -0,0 +1,651 @@
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use thiserror::Error;
+use tracing::{info, warn};
+use tracing_subscriber::EnvFilter;
+
+use crate::{template, Context};
+
+const CONFIG_RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("Failed to read config file {path}: {source}")]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Failed to parse config file {path}: {message}")]
+    Parse { path: PathBuf, message: String },
+    #[error("Unsupported config file extension for {0}, expected .yaml, .yml or .toml")]
+    UnsupportedFormat(PathBuf),
+    #[error("Invalid config value for '{field}': {reason}")]
+    Invalid { field: &'static str, reason: String },
+}
+
+/// Decides which nodes are managed by the controller
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum NodeSelectionMode {
+    /// Every node is managed unless it carries the skip label or annotation
+    #[default]
+    OptOut,
+    /// Only nodes carrying the managed label or annotation are managed
+    OptIn,
+}
+
+/// When stored taints are restored onto a node
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestoreTrigger {
+    /// As soon as the Node object appears
+    #[default]
+    Immediate,
+    /// Once the node reports the Ready condition
+    OnReady,
+    /// As soon as the Node object appears, removing the restore gate taint
+    /// the node registered with in the same patch
+    StartupTaint,
+}
+
+/// How records are laid out in ConfigMaps
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum StorageLayout {
+    /// One ConfigMap per node
+    #[default]
+    PerNode,
+    /// Records packed into shard ConfigMaps by node name hash prefix
+    Sharded,
+}
+
+/// How large records are compressed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum RecordCompression {
+    /// Always stored as JSON
+    None,
+    /// Gzipped into binary data above a size threshold
+    #[default]
+    Gzip,
+}
+
+/// How log lines are formatted
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogFormat {
+    /// Human readable lines
+    #[default]
+    Text,
+    /// One JSON object per line, with span and event fields at the top level
+    Json,
+}
+
+/// Controller configuration, loaded from an optional YAML/TOML file and
+/// overridden by environment variables and CLI flags
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Namespace for ConfigMap storage
+    pub configmap_namespace: String,
+    /// How records are laid out in ConfigMaps
+    pub storage_layout: StorageLayout,
+    /// Hex characters of the node name hash selecting the shard of a
+    /// record, for 16^n shards
+    pub shard_prefix_length: usize,
+    /// How large per-node records are compressed
+    pub record_compression: RecordCompression,
+    /// Snapshots of a node's taints kept in its record, 0 disables history
+    pub history_length: usize,
+    /// Files holding the keys records are encrypted with, the first one
+    /// encrypting and every one decrypting. Records are stored unencrypted
+    /// when empty.
+    pub record_encryption_key_files: Vec<PathBuf>,
+    /// Additional taint key prefixes that are never stored or restored
+    pub extra_protected_prefixes: Vec<String>,
+    /// Additional taint keys that are never stored or restored
+    pub extra_protected_keys: Vec<String>,
+    /// Base delay before retrying a failed reconcile
+    pub requeue_seconds: u64,
+    /// Upper bound for retry backoff
+    pub max_retry_seconds: u64,
+    /// How long node cleanup may keep failing before the finalizer is removed
+    pub finalizer_timeout_seconds: u64,
+    /// Address serving Prometheus metrics
+    pub metrics_addr: SocketAddr,
+    /// Address serving the unauthenticated admin endpoints, such as the log
+    /// level, local to the pod by default
+    pub admin_addr: SocketAddr,
+    /// Log filter directives, in `RUST_LOG` syntax
+    pub log_filter: String,
+    /// How log lines are formatted
+    pub log_format: LogFormat,
+    /// Label per-node metrics with the node name, which grows with the
+    /// number of nodes ever seen in autoscaled clusters
+    pub per_node_metrics: bool,
+    /// OTLP/HTTP collector endpoint traces are exported to, if any
+    pub otlp_endpoint: Option<String>,
+    /// How long in-flight reconciles may run after a shutdown signal
+    pub shutdown_grace_seconds: u64,
+    /// Which nodes are managed
+    pub node_selection_mode: NodeSelectionMode,
+    /// Label selector restricting which nodes are watched
+    pub node_label_selector: Option<String>,
+    /// Field selector restricting which nodes are watched
+    pub node_field_selector: Option<String>,
+    /// When stored taints are restored onto a node
+    pub restore_trigger: RestoreTrigger,
+    /// Value templates by taint key, rendered from the node's name, labels
+    /// and annotations when the taint is restored
+    pub taint_templates: BTreeMap<String, String>,
+    /// How long taints of a key last on a node before they are removed,
+    /// unless the node's expiry annotation says otherwise
+    pub taint_ttl_seconds: BTreeMap<String, u64>,
+    /// Re-apply records marked as enforced whenever their taints go missing
+    pub enforce_records: bool,
+    /// How often nodes with enforced records are re-checked
+    pub enforcement_resync_seconds: u64,
+    /// How often drift between nodes and records is published as metrics
+    pub drift_scan_seconds: u64,
+    /// Number of node cleanups within the window that pauses restores, 0 disables
+    pub circuit_breaker_max_cleanups: u32,
+    /// Window in which node cleanups are counted by the circuit breaker
+    pub circuit_breaker_window_seconds: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            configmap_namespace: "default".to_string(),
+            storage_layout: StorageLayout::PerNode,
+            shard_prefix_length: 2,
+            record_compression: RecordCompression::Gzip,
+            history_length: 5,
+            record_encryption_key_files: Vec::new(),
+            extra_protected_prefixes: Vec::new(),
+            extra_protected_keys: Vec::new(),
+            requeue_seconds: 2,
+            max_retry_seconds: 3600,
+            finalizer_timeout_seconds: 3600,
+            metrics_addr: SocketAddr::from(([0, 0, 0, 0], 8080)),
+            admin_addr: SocketAddr::from(([127, 0, 0, 1], 8081)),
+            log_filter: "info,kube=warn".to_string(),
+            log_format: LogFormat::Text,
+            per_node_metrics: true,
+            otlp_endpoint: None,
+            shutdown_grace_seconds: 25,
+            node_selection_mode: NodeSelectionMode::OptOut,
+            node_label_selector: None,
+            node_field_selector: None,
+            restore_trigger: RestoreTrigger::Immediate,
+            taint_templates: BTreeMap::new(),
+            taint_ttl_seconds: BTreeMap::new(),
+            enforce_records: false,
+            enforcement_resync_seconds: 300,
+            drift_scan_seconds: 300,
+            circuit_breaker_max_cleanups: 0,
+            circuit_breaker_window_seconds: 600,
+        }
+    }
+}
+
+/// Parse a `key=template` entry of the taint templates override
+fn parse_template_entry(entry: &str) -> Result<(String, String), String> {
+    entry
+        .split_once('=')
+        .map(|(key, value_template)| (key.to_string(), value_template.to_string()))
+        .ok_or_else(|| format!("expected key=template, got '{}'", entry))
+}
+
+/// Parse a `key=seconds` entry of the taint TTL override
+fn parse_ttl_entry(entry: &str) -> Result<(String, u64), String> {
+    let (key, seconds) = entry
+        .split_once('=')
+        .ok_or_else(|| format!("expected key=seconds, got '{}'", entry))?;
+    let seconds = seconds
+        .trim()
+        .parse()
+        .map_err(|e| format!("invalid seconds for {}: {}", key, e))?;
+    Ok((key.trim().to_string(), seconds))
+}
+
+/// Configuration set through environment variables or CLI flags, taking
+/// precedence over the config file
+#[derive(Clone, Debug, Default, clap::Args)]
+pub struct ConfigOverrides {
+    /// Path to a YAML or TOML config file, reloaded when it changes
+    #[arg(long = "config", env = "CONFIG_FILE")]
+    pub config_file: Option<PathBuf>,
+    /// Namespace for ConfigMap storage
+    #[arg(long, env = "CONFIGMAP_NAMESPACE")]
+    pub configmap_namespace: Option<String>,
+    /// How records are laid out in ConfigMaps
+    #[arg(long, env = "STORAGE_LAYOUT")]
+    pub storage_layout: Option<StorageLayout>,
+    /// Hex characters of the node name hash selecting the shard of a record
+    #[arg(long, env = "SHARD_PREFIX_LENGTH")]
+    pub shard_prefix_length: Option<usize>,
+    /// How large per-node records are compressed
+    #[arg(long, env = "RECORD_COMPRESSION")]
+    pub record_compression: Option<RecordCompression>,
+    /// Snapshots of a node's taints kept in its record
+    #[arg(long, env = "HISTORY_LENGTH")]
+    pub history_length: Option<usize>,
+    /// Files holding the record encryption keys, comma separated
+    #[arg(long, env = "RECORD_ENCRYPTION_KEY_FILES", value_delimiter = ',')]
+    pub record_encryption_key_files: Option<Vec<PathBuf>>,
+    /// Additional protected taint prefixes, comma separated
+    #[arg(long, env = "EXTRA_PROTECTED_TAINT_PREFIXES", value_delimiter = ',')]
+    pub extra_protected_prefixes: Option<Vec<String>>,
+    /// Additional protected taint keys, comma separated
+    #[arg(long, env = "EXTRA_PROTECTED_TAINT_KEYS", value_delimiter = ',')]
+    pub extra_protected_keys: Option<Vec<String>>,
+    /// Base delay before retrying a failed reconcile
+    #[arg(long, env = "REQUEUE_SECONDS")]
+    pub requeue_seconds: Option<u64>,
+    /// Upper bound for retry backoff
+    #[arg(long, env = "MAX_RETRY_SECONDS")]
+    pub max_retry_seconds: Option<u64>,
+    /// How long node cleanup may keep failing before the finalizer is removed
+    #[arg(long, env = "FINALIZER_TIMEOUT_SECONDS")]
+    pub finalizer_timeout_seconds: Option<u64>,
+    /// Address to serve Prometheus metrics on
+    #[arg(long, env = "METRICS_ADDR")]
+    pub metrics_addr: Option<SocketAddr>,
+    /// Address to serve admin endpoints such as `/log-level` on
+    #[arg(long, env = "ADMIN_ADDR")]
+    pub admin_addr: Option<SocketAddr>,
+    /// Log filter directives, e.g. `info,node_taint_preserver=debug`
+    #[arg(long, env = "RUST_LOG")]
+    pub log_filter: Option<String>,
+    /// How log lines are formatted
+    #[arg(long, env = "LOG_FORMAT")]
+    pub log_format: Option<LogFormat>,
+    /// Label per-node metrics with the node name
+    #[arg(long, env = "PER_NODE_METRICS")]
+    pub per_node_metrics: Option<bool>,
+    /// OTLP/HTTP collector endpoint to export traces to
+    #[arg(long, env = "OTEL_EXPORTER_OTLP_ENDPOINT")]
+    pub otlp_endpoint: Option<String>,
+    /// Seconds to let in-flight reconciles finish after SIGTERM/SIGINT
+    #[arg(long, env = "SHUTDOWN_GRACE_SECONDS")]
+    pub shutdown_grace_seconds: Option<u64>,
+    /// Which nodes are managed
+    #[arg(long, env = "NODE_SELECTION_MODE")]
+    pub node_selection_mode: Option<NodeSelectionMode>,
+    /// Label selector restricting which nodes are watched
+    #[arg(long, env = "NODE_LABEL_SELECTOR")]
+    pub node_label_selector: Option<String>,
+    /// Field selector restricting which nodes are watched
+    #[arg(long, env = "NODE_FIELD_SELECTOR")]
+    pub node_field_selector: Option<String>,
+    /// When stored taints are restored onto a node
+    #[arg(long, env = "RESTORE_TRIGGER")]
+    pub restore_trigger: Option<RestoreTrigger>,
+    /// Value templates of taint keys, comma separated key=template entries
+    #[arg(long, env = "TAINT_TEMPLATES", value_delimiter = ',', value_parser = parse_template_entry)]
+    pub taint_templates: Option<Vec<(String, String)>>,
+    /// Lifetimes of taint keys, comma separated key=seconds entries
+    #[arg(long, env = "TAINT_TTL_SECONDS", value_delimiter = ',', value_parser = parse_ttl_entry)]
+    pub taint_ttl_seconds: Option<Vec<(String, u64)>>,
+    /// Re-apply records marked as enforced whenever their taints go missing
+    #[arg(long, env = "ENFORCE_RECORDS")]
+    pub enforce_records: Option<bool>,
+    /// How often nodes with enforced records are re-checked
+    #[arg(long, env = "ENFORCEMENT_RESYNC_SECONDS")]
+    pub enforcement_resync_seconds: Option<u64>,
+    /// How often drift between nodes and records is published as metrics
+    #[arg(long, env = "DRIFT_SCAN_SECONDS")]
+    pub drift_scan_seconds: Option<u64>,
+    /// Number of node cleanups within the window that pauses restores, 0 disables
+    #[arg(long, env = "CIRCUIT_BREAKER_MAX_CLEANUPS")]
+    pub circuit_breaker_max_cleanups: Option<u32>,
+    /// Window in which node cleanups are counted by the circuit breaker
+    #[arg(long, env = "CIRCUIT_BREAKER_WINDOW_SECONDS")]
+    pub circuit_breaker_window_seconds: Option<u64>,
+}
+
+impl Config {
+    /// Load the config file, if any, apply overrides and validate the result
+    pub fn load(overrides: &ConfigOverrides) -> Result<Self, ConfigError> {
+        let mut config = match &overrides.config_file {
+            Some(path) => Self::from_file(path)?,
+            None => Self::default(),
+        };
+        config.apply(overrides);
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Parse a config file, picking the format from its extension
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let contents = std::fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        Self::parse(path, &contents)
+    }
+
+    fn parse(path: &Path, contents: &str) -> Result<Self, ConfigError> {
+        let parse_error = |message: String| ConfigError::Parse {
+            path: path.to_path_buf(),
+            message,
+        };
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("yaml" | "yml") => {
+                serde_yaml::from_str(contents).map_err(|e| parse_error(e.to_string()))
+            }
+            Some("toml") => toml::from_str(contents).map_err(|e| parse_error(e.to_string())),
+            _ => Err(ConfigError::UnsupportedFormat(path.to_path_buf())),
+        }
+    }
+
+    fn apply(&mut self, overrides: &ConfigOverrides) {
+        let o = overrides.clone();
+        if let Some(v) = o.configmap_namespace {
+            self.configmap_namespace = v;
+        }
+        if let Some(v) = o.storage_layout {
+            self.storage_layout = v;
+        }
+        if let Some(v) = o.shard_prefix_length {
+            self.shard_prefix_length = v;
+        }
+        if let Some(v) = o.record_compression {
+            self.record_compression = v;
+        }
+        if let Some(v) = o.history_length {
+            self.history_length = v;
+        }
+        if let Some(v) = o.record_encryption_key_files {
+            self.record_encryption_key_files = v;
+        }
+        if let Some(v) = o.extra_protected_prefixes {
+            self.extra_protected_prefixes = v;
+        }
+        if let Some(v) = o.extra_protected_keys {
+            self.extra_protected_keys = v;
+        }
+        if let Some(v) = o.requeue_seconds {
+            self.requeue_seconds = v;
+        }
+        if let Some(v) = o.max_retry_seconds {
+            self.max_retry_seconds = v;
+        }
+        if let Some(v) = o.finalizer_timeout_seconds {
+            self.finalizer_timeout_seconds = v;
+        }
+        if let Some(v) = o.metrics_addr {
+            self.metrics_addr = v;
+        }
+        if let Some(v) = o.admin_addr {
+            self.admin_addr = v;
+        }
+        if let Some(v) = o.log_filter {
+            self.log_filter = v;
+        }
+        if let Some(v) = o.log_format {
+            self.log_format = v;
+        }
+        if let Some(v) = o.per_node_metrics {
+            self.per_node_metrics = v;
+        }
+        if let Some(v) = o.otlp_endpoint {
+            self.otlp_endpoint = Some(v);
+        }
+        if let Some(v) = o.shutdown_grace_seconds {
+            self.shutdown_grace_seconds = v;
+        }
+        if let Some(v) = o.node_selection_mode {
+            self.node_selection_mode = v;
+        }
+        if let Some(v) = o.node_label_selector {
+            self.node_label_selector = Some(v);
+        }
+        if let Some(v) = o.node_field_selector {
+            self.node_field_selector = Some(v);
+        }
+        if let Some(v) = o.restore_trigger {
+            self.restore_trigger = v;
+        }
+        if let Some(v) = o.taint_templates {
+            self.taint_templates = v.into_iter().collect();
+        }
+        if let Some(v) = o.taint_ttl_seconds {
+            self.taint_ttl_seconds = v.into_iter().collect();
+        }
+        if let Some(v) = o.enforce_records {
+            self.enforce_records = v;
+        }
+        if let Some(v) = o.enforcement_resync_seconds {
+            self.enforcement_resync_seconds = v;
+        }
+        if let Some(v) = o.drift_scan_seconds {
+            self.drift_scan_seconds = v;
+        }
+        if let Some(v) = o.circuit_breaker_max_cleanups {
+            self.circuit_breaker_max_cleanups = v;
+        }
+        if let Some(v) = o.circuit_breaker_window_seconds {
+            self.circuit_breaker_window_seconds = v;
+        }
+
+        // Tolerate the stray whitespace and empty entries of comma separated lists
+        for list in [
+            &mut self.extra_protected_prefixes,
+            &mut self.extra_protected_keys,
+        ] {
+            *list = list
+                .iter()
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect();
+        }
+        for value in [
+            &mut self.node_label_selector,
+            &mut self.node_field_selector,
+            &mut self.otlp_endpoint,
+        ] {
+            if value.as_ref().is_some_and(|s| s.trim().is_empty()) {
+                *value = None;
+            }
+        }
+    }
+
+    /// Check the config for values the controller cannot run with
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |field, reason: &str| {
+            Err(ConfigError::Invalid {
+                field,
+                reason: reason.to_string(),
+            })
+        };
+
+        let namespace = &self.configmap_namespace;
+        if namespace.is_empty()
+            || namespace.len() > 63
+            || !namespace
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+            || namespace.starts_with('-')
+            || namespace.ends_with('-')
+        {
+            return invalid(
+                "configmap_namespace",
+                "must be a valid namespace name (lowercase alphanumerics and '-', at most 63 characters)",
+            );
+        }
+        if !(1..=4).contains(&self.shard_prefix_length) {
+            return invalid("shard_prefix_length", "must be between 1 and 4");
+        }
+        if self.history_length > 50 {
+            return invalid("history_length", "must be at most 50");
+        }
+        for (key, value_template) in &self.taint_templates {
+            if let Err(e) = template::validate(value_template) {
+                return invalid("taint_templates", &format!("{}: {}", key, e));
+            }
+        }
+        if let Some((key, _)) = self.taint_ttl_seconds.iter().find(|(_, ttl)| **ttl == 0) {
+            return invalid(
+                "taint_ttl_seconds",
+                &format!("{}: must be greater than 0", key),
+            );
+        }
+        if let Err(e) = EnvFilter::try_new(&self.log_filter) {
+            return invalid("log_filter", &e.to_string());
+        }
+        if self.requeue_seconds == 0 {
+            return invalid("requeue_seconds", "must be greater than 0");
+        }
+        if self.max_retry_seconds < self.requeue_seconds {
+            return invalid(
+                "max_retry_seconds",
+                "must be greater than or equal to requeue_seconds",
+            );
+        }
+        if self.finalizer_timeout_seconds == 0 {
+            return invalid("finalizer_timeout_seconds", "must be greater than 0");
+        }
+        if self.enforcement_resync_seconds == 0 {
+            return invalid("enforcement_resync_seconds", "must be greater than 0");
+        }
+        if self.drift_scan_seconds == 0 {
+            return invalid("drift_scan_seconds", "must be greater than 0");
+        }
+        if self.circuit_breaker_window_seconds == 0 {
+            return invalid("circuit_breaker_window_seconds", "must be greater than 0");
+        }
+        Ok(())
+    }
+
+    /// Take the settings that can safely change at runtime from `new`,
+    /// warning about changed settings that only apply after a restart
+    fn reloaded(&self, new: Config) -> Config {
+        let restart_only = [
+            (
+                "configmap_namespace",
+                self.configmap_namespace != new.configmap_namespace,
+            ),
+            ("storage_layout", self.storage_layout != new.storage_layout),
+            (
+                "shard_prefix_length",
+                self.shard_prefix_length != new.shard_prefix_length,
+            ),
+            (
+                "record_encryption_key_files",
+                self.record_encryption_key_files != new.record_encryption_key_files,
+            ),
+            ("metrics_addr", self.metrics_addr != new.metrics_addr),
+            ("admin_addr", self.admin_addr != new.admin_addr),
+            ("log_filter", self.log_filter != new.log_filter),
+            ("log_format", self.log_format != new.log_format),
+            ("otlp_endpoint", self.otlp_endpoint != new.otlp_endpoint),
+            (
+                "shutdown_grace_seconds",
+                self.shutdown_grace_seconds != new.shutdown_grace_seconds,
+            ),
+            (
+                "node_selection_mode",
+                self.node_selection_mode != new.node_selection_mode,
+            ),
+            (
+                "node_label_selector",
+                self.node_label_selector != new.node_label_selector,
+            ),
+            (
+                "node_field_selector",
+                self.node_field_selector != new.node_field_selector,
+            ),
+        ];
+        for (field, changed) in restart_only {
+            if changed {
+                warn!(
+                    "Config field '{}' changed, the new value applies after a restart",
+                    field
+                );
+            }
+        }
+
+        Config {
+            configmap_namespace: self.configmap_namespace.clone(),
+            storage_layout: self.storage_layout,
+            shard_prefix_length: self.shard_prefix_length,
+            record_encryption_key_files: self.record_encryption_key_files.clone(),
+            metrics_addr: self.metrics_addr,
+            admin_addr: self.admin_addr,
+            log_filter: self.log_filter.clone(),
+            log_format: self.log_format,
+            otlp_endpoint: self.otlp_endpoint.clone(),
+            shutdown_grace_seconds: self.shutdown_grace_seconds,
+            node_selection_mode: self.node_selection_mode,
+            node_label_selector: self.node_label_selector.clone(),
+            node_field_selector: self.node_field_selector.clone(),
+            ..new
+        }
+    }
+
+    pub fn finalizer_timeout(&self) -> Duration {
+        Duration::from_secs(self.finalizer_timeout_seconds)
+    }
+
+    pub fn shutdown_grace(&self) -> Duration {
+        Duration::from_secs(self.shutdown_grace_seconds)
+    }
+
+    pub fn enforcement_resync(&self) -> Duration {
+        Duration::from_secs(self.enforcement_resync_seconds)
+    }
+
+    pub fn drift_scan_interval(&self) -> Duration {
+        Duration::from_secs(self.drift_scan_seconds)
+    }
+
+    pub fn circuit_breaker_window(&self) -> Duration {
+        Duration::from_secs(self.circuit_breaker_window_seconds)
+    }
+}
+
+/// Poll the config file and apply changes to the running controller.
+/// Polling the content rather than using inotify also picks up the
+/// symlink swaps of files mounted from a ConfigMap.
+pub async fn watch_config_file(ctx: Arc<Context>, overrides: ConfigOverrides) {
+    let Some(path) = overrides.config_file.clone() else {
+        return;
+    };
+    let digest = |path: &Path| {
+        std::fs::read(path)
+            .ok()
+            .map(|contents| Sha256::digest(contents).to_vec())
+    };
+
+    let mut last_digest = digest(&path);
+    let mut interval = tokio::time::interval(CONFIG_RELOAD_INTERVAL);
+    loop {
+        interval.tick().await;
+        let current_digest = digest(&path);
+        if current_digest == last_digest {
+            continue;
+        }
+        last_digest = current_digest;
+
+        match Config::load(&overrides) {
+            Ok(new) => {
+                let current = ctx.config();
+                let reloaded = current.reloaded(new);
+                if reloaded != *current {
+                    ctx.set_config(reloaded);
+                    info!("Reloaded config from {}", path.display());
+                }
+            }
+            Err(e) => warn!(error = %e, "Ignoring invalid config change"),
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use crate::{config::ConfigError, Error, Result};
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256Gcm, Nonce,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+
+const NONCE_BYTES: usize = 12;
+
+/// Keys records are encrypted with. The first key seals new records and
+/// every key opens them, so a key can be rotated by adding the new key
+/// first and keeping the old one until all records were re-encrypted.
+#[derive(Clone, Default)]
+pub struct Keyring {
+    keys: Vec<(String, Aes256Gcm)>,
+}
+
+/// Taints encrypted with a random data key, which is itself encrypted with
+/// a keyring key. Both ciphertexts are base64 with the nonce prepended.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct Envelope {
+    /// Identifies the keyring key the data key is encrypted with
+    pub key_id: String,
+    wrapped_key: String,
+    ciphertext: String,
+}
+
+impl Keyring {
+    /// Load base64-encoded 256-bit keys from files, such as a mounted Secret
+    pub fn load(paths: &[PathBuf]) -> Result<Self, ConfigError> {
+        let mut keys = Vec::new();
+        for path in paths {
+            let contents = std::fs::read_to_string(path).map_err(|source| ConfigError::Read {
+                path: path.clone(),
+                source,
+            })?;
+            let key: [u8; 32] = STANDARD
+                .decode(contents.trim())
+                .ok()
+                .and_then(|key| key.try_into().ok())
+                .ok_or_else(|| ConfigError::Invalid {
+                    field: "record_encryption_key_files",
+                    reason: format!("{} must hold a base64-encoded 32 byte key", path.display()),
+                })?;
+            keys.push(key);
+        }
+        Ok(Self::from_keys(&keys))
+    }
+
+    /// Keyring of raw 256-bit keys, the first one sealing new records
+    pub fn from_keys(keys: &[[u8; 32]]) -> Self {
+        let keys = keys
+            .iter()
+            .map(|key| {
+                let id = hex::encode(Sha256::digest(key))[..8].to_string();
+                (id, Aes256Gcm::new(key.into()))
+            })
+            .collect();
+        Self { keys }
+    }
+
+    /// Whether records are encrypted
+    pub fn is_enabled(&self) -> bool {
+        !self.keys.is_empty()
+    }
+
+    /// ID of the key sealing new records
+    pub(crate) fn primary_id(&self) -> Option<&str> {
+        self.keys.first().map(|(id, _)| id.as_str())
+    }
+
+    /// Encrypt the taints of a node with the primary key. The node name is
+    /// authenticated, so an envelope cannot be moved to another node's record.
+    pub(crate) fn seal(&self, node_name: &str, plaintext: &[u8]) -> Result<Envelope> {
+        let (key_id, cipher) = self
+            .keys
+            .first()
+            .ok_or_else(|| Error::Encryption("no encryption key configured".to_string()))?;
+        let data_key = Aes256Gcm::generate_key(OsRng);
+        Ok(Envelope {
+            key_id: key_id.clone(),
+            wrapped_key: encrypt(cipher, &data_key, node_name)?,
+            ciphertext: encrypt(&Aes256Gcm::new(&data_key), plaintext, node_name)?,
+        })
+    }
+
+    /// Decrypt the taints of a node with the key the envelope names
+    pub(crate) fn open(&self, node_name: &str, envelope: &Envelope) -> Result<Vec<u8>> {
+        let (_, cipher) = self
+            .keys
+            .iter()
+            .find(|(id, _)| *id == envelope.key_id)
+            .ok_or_else(|| {
+                Error::Encryption(format!("no configured key with ID {}", envelope.key_id))
+            })?;
+        let data_key = decrypt(cipher, &envelope.wrapped_key, node_name)?;
+        let data_cipher = Aes256Gcm::new_from_slice(&data_key)
+            .map_err(|_| Error::Encryption("invalid data key".to_string()))?;
+        decrypt(&data_cipher, &envelope.ciphertext, node_name)
+    }
+}
+
+/// Encrypt with a random nonce, returning the base64 nonce and ciphertext
+fn encrypt(cipher: &Aes256Gcm, plaintext: &[u8], node_name: &str) -> Result<String> {
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let payload = Payload {
+        msg: plaintext,
+        aad: node_name.as_bytes(),
+    };
+    let ciphertext = cipher
+        .encrypt(&nonce, payload)
+        .map_err(|_| Error::Encryption("encryption failed".to_string()))?;
+    Ok(STANDARD.encode([nonce.as_slice(), &ciphertext].concat()))
+}
+
+/// Decrypt the output of `encrypt`
+fn decrypt(cipher: &Aes256Gcm, sealed: &str, node_name: &str) -> Result<Vec<u8>> {
+    let sealed = STANDARD
+        .decode(sealed)
+        .map_err(|e| Error::Encryption(e.to_string()))?;
+    if sealed.len() < NONCE_BYTES {
+        return Err(Error::Encryption("ciphertext too short".to_string()));
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_BYTES);
+    let payload = Payload {
+        msg: ciphertext,
+        aad: node_name.as_bytes(),
+    };
+    cipher
+        .decrypt(Nonce::from_slice(nonce), payload)
+        .map_err(|_| {
+            Error::Encryption("decryption failed, wrong key or tampered record".to_string())
+        })
+}
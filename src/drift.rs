@@ -0,0 +1,198 @@
+use crate::{
+    expiry, filter_protected_taints, is_node_managed, node_label, observe_api_call,
+    record_list_params,
+    storage::{record_revision, records_by_node, Record},
+    template, Config, Context, Result, LEGACY_RESTORED_VALUE, MANAGED_NODES,
+    NODES_AWAITING_RESTORE, NODES_WITH_DRIFT, NODE_TAINT_DRIFT, RESTORED_ANNOTATION_KEY,
+    STORED_RECORDS,
+};
+use k8s_openapi::{
+    api::core::v1::{Node, Taint},
+    chrono::Utc,
+};
+use kube::api::{Api, ResourceExt};
+use serde::Serialize;
+use std::{collections::BTreeMap, sync::Arc};
+use tracing::warn;
+
+/// Difference between a live node's custom taints and its stored record
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct NodeDrift {
+    pub node: String,
+    /// Taints on the node that are missing from the record
+    pub added: Vec<Taint>,
+    /// Taints in the record that are missing from the node
+    pub removed: Vec<Taint>,
+    /// Taints whose value or effect differs, as (stored, live)
+    pub changed: Vec<(Taint, Taint)>,
+}
+
+impl NodeDrift {
+    /// Check whether the node matches its record
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Compare live custom taints with the stored ones, matching taints by key
+fn diff_taints(node_name: &str, live: Vec<Taint>, stored: Vec<Taint>) -> NodeDrift {
+    let mut drift = NodeDrift {
+        node: node_name.to_string(),
+        ..Default::default()
+    };
+
+    for taint in &live {
+        match stored.iter().find(|t| t.key == taint.key) {
+            None => drift.added.push(taint.clone()),
+            Some(stored_taint) => {
+                if stored_taint.value != taint.value || stored_taint.effect != taint.effect {
+                    drift.changed.push((stored_taint.clone(), taint.clone()));
+                }
+            }
+        }
+    }
+
+    for taint in stored {
+        if !live.iter().any(|t| t.key == taint.key) {
+            drift.removed.push(taint);
+        }
+    }
+
+    drift
+}
+
+/// Live nodes within the configured selectors, and stored records by node name
+async fn list_nodes_and_records(ctx: &Context) -> Result<(Vec<Node>, BTreeMap<String, Record>)> {
+    let node_api: Api<Node> = Api::all(ctx.client.clone());
+    let nodes = observe_api_call("nodes", "list", node_api.list(&ctx.node_list_params()))
+        .await?
+        .items;
+
+    let cm_api = ctx.cm_api();
+    let records = observe_api_call("configmaps", "list", cm_api.list(&record_list_params()))
+        .await?
+        .items;
+    let records = records_by_node(ctx.keyring(), &records);
+
+    Ok((nodes, records))
+}
+
+/// Compare every managed live node that has a stored record with that record.
+/// Nodes without a record are not reported.
+pub async fn detect_drift(ctx: &Context) -> Result<Vec<NodeDrift>> {
+    let (nodes, records) = list_nodes_and_records(ctx).await?;
+    Ok(drift_report(&ctx.config(), &nodes, &records))
+}
+
+fn drift_report(
+    config: &Config,
+    nodes: &[Node],
+    records: &BTreeMap<String, Record>,
+) -> Vec<NodeDrift> {
+    let mut report = Vec::new();
+    for node in nodes {
+        if !is_node_managed(node, config.node_selection_mode) {
+            continue;
+        }
+        let node_name = node.name_any();
+        let Some(record) = records.get(&node_name) else {
+            continue;
+        };
+
+        let live = node
+            .spec
+            .as_ref()
+            .and_then(|spec| spec.taints.clone())
+            .unwrap_or_default();
+        let live = filter_protected_taints(live, config);
+        // Expired taints are not restored, so their absence is no drift, and
+        // templated taints are compared with the value they would be restored with
+        let live_expiry = expiry::annotated_expiry(node);
+        let now = Utc::now();
+        let stored = record
+            .taints
+            .iter()
+            .filter(|taint| {
+                let expires_at = live_expiry
+                    .get(&taint.key)
+                    .or_else(|| record.expiry.get(&taint.key));
+                !expiry::is_expired(expires_at, now)
+            })
+            .map(|taint| {
+                template::render_taint(taint.clone(), &config.taint_templates, node)
+                    .unwrap_or_else(|_| taint.clone())
+            })
+            .collect();
+        let stored = filter_protected_taints(stored, config);
+
+        report.push(diff_taints(&node_name, live, stored));
+    }
+
+    report
+}
+
+/// Publish the number of records, managed nodes and managed nodes whose
+/// record was not restored yet (e.g. waiting for readiness or the breaker)
+fn record_inventory_metrics(config: &Config, nodes: &[Node], records: &BTreeMap<String, Record>) {
+    let managed: Vec<&Node> = nodes
+        .iter()
+        .filter(|node| is_node_managed(node, config.node_selection_mode))
+        .collect();
+    let awaiting_restore = managed
+        .iter()
+        .filter(|node| {
+            let Some(record) = records.get(&node.name_any()) else {
+                return false;
+            };
+            match node.annotations().get(RESTORED_ANNOTATION_KEY) {
+                Some(restored) => {
+                    restored != LEGACY_RESTORED_VALUE && *restored != record_revision(Some(record))
+                }
+                None => true,
+            }
+        })
+        .count();
+
+    STORED_RECORDS.set(records.len() as i64);
+    MANAGED_NODES.set(managed.len() as i64);
+    NODES_AWAITING_RESTORE.set(awaiting_restore as i64);
+}
+
+/// Publish a drift report as Prometheus gauges
+pub fn record_drift_metrics(config: &Config, report: &[NodeDrift]) {
+    NODE_TAINT_DRIFT.reset();
+    let mut nodes_with_drift = 0;
+    for drift in report.iter().filter(|d| !d.is_empty()) {
+        nodes_with_drift += 1;
+        for (kind, count) in [
+            ("added", drift.added.len()),
+            ("removed", drift.removed.len()),
+            ("changed", drift.changed.len()),
+        ] {
+            // Without per-node labels, the counts of all nodes are summed
+            NODE_TAINT_DRIFT
+                .with_label_values(&[node_label(config, &drift.node), kind])
+                .add(count as i64);
+        }
+    }
+    NODES_WITH_DRIFT.set(nodes_with_drift);
+}
+
+/// Periodically detect drift and publish it, along with the number of
+/// records and managed nodes, as Prometheus gauges
+pub async fn run_drift_scans(ctx: Arc<Context>) {
+    loop {
+        let config = ctx.config();
+        match list_nodes_and_records(&ctx).await {
+            Ok((nodes, records)) => {
+                ctx.retain_known_taints(&nodes);
+                record_inventory_metrics(&config, &nodes, &records);
+                let report = drift_report(&config, &nodes, &records);
+                record_drift_metrics(&config, &report);
+            }
+            Err(e) => warn!(error = ?e, "Drift scan failed"),
+        }
+        // Re-read every time so config reloads apply to the next scan
+        tokio::time::sleep(ctx.config().drift_scan_interval()).await;
+    }
+}
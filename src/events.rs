@@ -0,0 +1,111 @@
+use crate::{EVENTS_SUPPRESSED_TOTAL, SERVICE_NAME};
+use k8s_openapi::api::core::v1::ObjectReference;
+use kube::{
+    runtime::events::{Event, EventType, Recorder, Reporter},
+    Client,
+};
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tracing::{debug, warn};
+
+/// Events an object may emit in a burst before being rate limited
+const EVENT_BURST: u32 = 10;
+/// How often a rate limited object regains one event
+const EVENT_REFILL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Token bucket of a single object
+struct Bucket {
+    tokens: u32,
+    refilled_at: Instant,
+}
+
+/// Publishes events.k8s.io/v1 Events. Repeated reasons about the same object
+/// are aggregated into a series by the kube recorder, and each object is
+/// rate limited so a flapping node cannot flood the API server.
+pub struct EventRecorder {
+    recorder: Recorder,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl EventRecorder {
+    pub fn new(client: Client) -> Self {
+        let reporter = Reporter {
+            controller: SERVICE_NAME.to_string(),
+            instance: std::env::var("HOSTNAME").ok(),
+        };
+        Self {
+            recorder: Recorder::new(client, reporter),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Publish an Event about an object, unless the object exceeded its
+    /// rate limit. Failures are logged and never fail the caller, the
+    /// returned outcome is one of `published`, `suppressed` or `failed`.
+    pub async fn publish(
+        &self,
+        regarding: &ObjectReference,
+        event_type: EventType,
+        reason: &str,
+        note: &str,
+    ) -> &'static str {
+        let object = regarding
+            .uid
+            .clone()
+            .or_else(|| regarding.name.clone())
+            .unwrap_or_default();
+        if !self.allow(&object, Instant::now()) {
+            debug!(
+                object = regarding.name.as_deref(),
+                reason, "Suppressed event: {}", note
+            );
+            EVENTS_SUPPRESSED_TOTAL.with_label_values(&[reason]).inc();
+            return "suppressed";
+        }
+
+        let event = Event {
+            type_: event_type,
+            reason: reason.to_string(),
+            note: Some(note.to_string()),
+            action: "Reconcile".to_string(),
+            secondary: None,
+        };
+        match self.recorder.publish(&event, regarding).await {
+            Ok(()) => "published",
+            Err(e) => {
+                warn!(object = regarding.name.as_deref(), reason, error = ?e, "Failed to publish event");
+                "failed"
+            }
+        }
+    }
+
+    /// Take a token from the object's bucket, refilling it first
+    fn allow(&self, object: &str, now: Instant) -> bool {
+        let mut buckets = self.buckets.lock().unwrap();
+        // Full buckets carry no state, so they are dropped to bound the map
+        buckets.retain(|_, b| {
+            let refilled =
+                now.duration_since(b.refilled_at).as_secs() / EVENT_REFILL_INTERVAL.as_secs();
+            b.tokens as u64 + refilled < EVENT_BURST as u64
+        });
+
+        let bucket = buckets.entry(object.to_string()).or_insert(Bucket {
+            tokens: EVENT_BURST,
+            refilled_at: now,
+        });
+        let refilled = (now.duration_since(bucket.refilled_at).as_secs()
+            / EVENT_REFILL_INTERVAL.as_secs()) as u32;
+        if refilled > 0 {
+            bucket.tokens = (bucket.tokens + refilled).min(EVENT_BURST);
+            bucket.refilled_at += EVENT_REFILL_INTERVAL * refilled;
+        }
+        if bucket.tokens == 0 {
+            return false;
+        }
+        bucket.tokens -= 1;
+        true
+    }
+}
@@ -0,0 +1,210 @@
+use crate::{
+    emit_event, is_taint_protected, node_label, observe_api_call,
+    storage::{get_record, store_expired},
+    Config, Context, Error, Result, TAINTS_EXPIRED_TOTAL, TAINT_EXPIRY_ANNOTATION,
+};
+use k8s_openapi::{
+    api::core::v1::{Node, Taint},
+    apimachinery::pkg::apis::meta::v1::Time,
+    chrono::{DateTime, SecondsFormat, TimeDelta, Utc},
+};
+use kube::{
+    api::{Api, Patch, PatchParams, ResourceExt},
+    runtime::events::EventType,
+};
+use std::{collections::BTreeMap, time::Duration};
+use tracing::{debug, info, warn};
+
+/// Outcome of checking a node for expired taints
+pub(crate) enum Expiry {
+    /// The node was patched, and is reconciled again once the change is seen
+    Patched,
+    /// Nothing expired, the next taint on the node expires after the
+    /// duration, if any
+    Pending(Option<Duration>),
+}
+
+/// Expiry times by taint key from the expiry annotation of a node, a comma
+/// separated list of `key=<RFC 3339 time>` entries. Invalid entries are
+/// ignored.
+pub(crate) fn annotated_expiry(node: &Node) -> BTreeMap<String, Time> {
+    let Some(value) = node.annotations().get(TAINT_EXPIRY_ANNOTATION) else {
+        return BTreeMap::new();
+    };
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .filter_map(|entry| {
+            let parsed = entry.split_once('=').and_then(|(key, time)| {
+                let time = DateTime::parse_from_rfc3339(time.trim()).ok()?;
+                Some((key.trim().to_string(), Time(time.with_timezone(&Utc))))
+            });
+            if parsed.is_none() {
+                warn!(node = %node.name_any(), entry, "Ignoring invalid taint expiry entry");
+            }
+            parsed
+        })
+        .collect()
+}
+
+/// Value of the expiry annotation for the given expiry times
+pub(crate) fn format_expiry(expiry: &BTreeMap<String, Time>) -> String {
+    expiry
+        .iter()
+        .map(|(key, time)| {
+            format!(
+                "{}={}",
+                key,
+                time.0.to_rfc3339_opts(SecondsFormat::Secs, true)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Expiry time given to a taint by the TTL configured for its key, counted
+/// from when the taint was added, or from now when that is unknown
+fn ttl_expiry(taint: &Taint, config: &Config, now: DateTime<Utc>) -> Option<Time> {
+    let ttl = *config.taint_ttl_seconds.get(&taint.key)?;
+    let added = taint.time_added.as_ref().map_or(now, |time| time.0);
+    let ttl = TimeDelta::try_seconds(i64::try_from(ttl).ok()?)?;
+    Some(Time(added.checked_add_signed(ttl)?))
+}
+
+/// Whether an expiry time has passed
+pub(crate) fn is_expired(expiry: Option<&Time>, now: DateTime<Utc>) -> bool {
+    expiry.is_some_and(|time| time.0 <= now)
+}
+
+/// Expiry times of taints of a node, to store with its record: the ones
+/// from the node's expiry annotation, or given by the configured TTLs
+pub(crate) fn taint_expiry(
+    node: &Node,
+    taints: &[Taint],
+    config: &Config,
+) -> BTreeMap<String, Time> {
+    let now = Utc::now();
+    let annotated = annotated_expiry(node);
+    taints
+        .iter()
+        .filter_map(|taint| {
+            let time = annotated
+                .get(&taint.key)
+                .cloned()
+                .or_else(|| ttl_expiry(taint, config, now))?;
+            Some((taint.key.clone(), time))
+        })
+        .collect()
+}
+
+/// Remove the custom taints of a live node whose expiry time has passed.
+/// Taints of keys with a configured TTL get their expiry time written to
+/// the node's expiry annotation first, and entries of keys no longer on the
+/// node are dropped from it, so a taint added again with the key is stamped
+/// anew. Enforced records keep the expiry times of the taints removed, so
+/// they do not re-apply them.
+pub(crate) async fn expire_taints(ctx: &Context, node: &Node) -> Result<Expiry> {
+    let config = ctx.config();
+    let node_name = node.name_any();
+    let now = Utc::now();
+    let live_taints = node
+        .spec
+        .as_ref()
+        .and_then(|spec| spec.taints.clone())
+        .unwrap_or_default();
+
+    let annotated = annotated_expiry(node);
+    let mut expiry = BTreeMap::new();
+    let mut stamped = Vec::new();
+    for taint in &live_taints {
+        // An entry older than the taint was left by an earlier taint with
+        // the key
+        let entry = annotated.get(&taint.key).filter(|time| {
+            taint
+                .time_added
+                .as_ref()
+                .is_none_or(|added| added.0 < time.0)
+        });
+        if let Some(time) = entry {
+            expiry.insert(taint.key.clone(), time.clone());
+        } else if is_taint_protected(taint, &config) {
+            continue;
+        } else if let Some(time) = ttl_expiry(taint, &config, now) {
+            expiry.insert(taint.key.clone(), time);
+            stamped.push(taint.key.clone());
+        }
+    }
+
+    let (expired, kept): (Vec<Taint>, Vec<Taint>) = live_taints.into_iter().partition(|taint| {
+        !is_taint_protected(taint, &config) && is_expired(expiry.get(&taint.key), now)
+    });
+    let expired_times: BTreeMap<String, Time> = expired
+        .iter()
+        .filter_map(|taint| Some((taint.key.clone(), expiry.remove(&taint.key)?)))
+        .collect();
+    if expired.is_empty() && expiry == annotated {
+        let next = expiry
+            .values()
+            .filter(|time| time.0 > now)
+            .min()
+            .map(|time| (time.0 - now).to_std().unwrap_or_default());
+        return Ok(Expiry::Pending(next));
+    }
+
+    // Stored before the taints are removed, so a failed write is retried
+    if !expired_times.is_empty() && config.enforce_records {
+        let record = get_record(ctx, &node_name).await?;
+        if record.is_some_and(|record| record.enforced) {
+            store_expired(ctx, &node_name, &expired_times).await?;
+        }
+    }
+
+    let taints = if kept.is_empty() { None } else { Some(kept) };
+    // resourceVersion makes the merge patch fail on concurrent taint edits
+    let patch_payload = serde_json::json!({
+        "metadata": {
+            "resourceVersion": node.resource_version(),
+            "annotations": {
+                TAINT_EXPIRY_ANNOTATION: (!expiry.is_empty()).then(|| format_expiry(&expiry))
+            }
+        },
+        "spec": {
+            "taints": taints
+        }
+    });
+    let node_api: Api<Node> = Api::all(ctx.client.clone());
+    observe_api_call(
+        "nodes",
+        "patch",
+        node_api.patch(
+            &node_name,
+            &PatchParams::default(),
+            &Patch::Merge(&patch_payload),
+        ),
+    )
+    .await
+    .map_err(Error::Kube)?;
+
+    if !stamped.is_empty() {
+        debug!(
+            node = %node_name,
+            phase = "apply",
+            action = "stamp_expiry",
+            "Set expiry times of taints with a TTL: {}",
+            stamped.join(", ")
+        );
+    }
+    if !expired.is_empty() {
+        let keys: Vec<&str> = expired.iter().map(|t| t.key.as_str()).collect();
+        let message = format!("Removed expired taints: {}", keys.join(", "));
+        info!(node = %node_name, phase = "apply", action = "expire", "{}", message);
+        for key in &keys {
+            TAINTS_EXPIRED_TOTAL
+                .with_label_values(&[node_label(&config, &node_name), key])
+                .inc();
+        }
+        emit_event(ctx, node, "TaintsExpired", &message, EventType::Normal).await;
+    }
+    Ok(Expiry::Patched)
+}
@@ -0,0 +1,119 @@
+use crate::{
+    emit_event,
+    expiry::{annotated_expiry, format_expiry},
+    filter_protected_taints, is_taint_protected, observe_api_call, prepare_taint,
+    storage::{get_record, Snapshot},
+    Context, Error, PreparedTaint, Result, TAINT_EXPIRY_ANNOTATION,
+};
+use k8s_openapi::{
+    api::core::v1::{Node, Taint},
+    chrono::Utc,
+};
+use kube::{
+    api::{Api, Patch, PatchParams},
+    runtime::events::EventType,
+};
+use tracing::info;
+
+/// Snapshots of a node's taints kept in its record, newest first
+pub async fn node_history(ctx: &Context, node_name: &str) -> Result<Vec<Snapshot>> {
+    let record = get_record(ctx, node_name).await?;
+    Ok(record.map(|record| record.history).unwrap_or_default())
+}
+
+/// Replace the custom taints of a live node with those of a snapshot from
+/// its history, 0 being the newest. Protected taints on the node are kept.
+/// Like restores, taints whose expiry time passed are skipped, the others
+/// keep their expiry time, and values are rendered from their templates;
+/// the node is left alone when a template fails. Returns the taints of the
+/// node after the restore.
+pub async fn restore_snapshot(
+    ctx: &Context,
+    node_name: &str,
+    index: usize,
+    dry_run: bool,
+) -> Result<Vec<Taint>> {
+    let config = ctx.config();
+    let snapshot = node_history(ctx, node_name)
+        .await?
+        .into_iter()
+        .nth(index)
+        .ok_or_else(|| Error::SnapshotNotFound(node_name.to_string(), index))?;
+
+    let node_api: Api<Node> = Api::all(ctx.client.clone());
+    let node = observe_api_call("nodes", "get", node_api.get(node_name)).await?;
+    let mut taints: Vec<Taint> = node
+        .spec
+        .as_ref()
+        .and_then(|spec| spec.taints.clone())
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|taint| is_taint_protected(taint, &config))
+        .collect();
+    let mut expiry = annotated_expiry(&node);
+    expiry.retain(|key, _| taints.iter().any(|taint| &taint.key == key));
+
+    let now = Utc::now();
+    let mut expired_keys = Vec::new();
+    let mut template_errors = Vec::new();
+    for taint in filter_protected_taints(snapshot.taints, &config) {
+        let key = taint.key.clone();
+        let expires_at = snapshot.expiry.get(&key);
+        match prepare_taint(taint, expires_at, &config.taint_templates, &node, now) {
+            PreparedTaint::Ready(taint) => {
+                if let Some(time) = expires_at {
+                    expiry.insert(key, time.clone());
+                }
+                taints.push(taint);
+            }
+            PreparedTaint::Expired => expired_keys.push(key),
+            PreparedTaint::Failed(e) => template_errors.push(format!("{}: {}", key, e)),
+        }
+    }
+    if !template_errors.is_empty() {
+        return Err(Error::TemplateFailed(
+            node_name.to_string(),
+            template_errors.join(", "),
+        ));
+    }
+
+    // resourceVersion makes the merge patch fail on concurrent taint edits
+    let patch_payload = serde_json::json!({
+        "metadata": {
+            "resourceVersion": node.metadata.resource_version,
+            "annotations": {
+                TAINT_EXPIRY_ANNOTATION: (!expiry.is_empty()).then(|| format_expiry(&expiry))
+            }
+        },
+        "spec": {
+            "taints": taints
+        }
+    });
+    let patch_params = PatchParams {
+        dry_run,
+        ..Default::default()
+    };
+    observe_api_call(
+        "nodes",
+        "patch",
+        node_api.patch(node_name, &patch_params, &Patch::Merge(&patch_payload)),
+    )
+    .await?;
+
+    let mut message = format!(
+        "Restored taints of snapshot {} captured at {}",
+        index,
+        snapshot.captured_at.0.to_rfc3339()
+    );
+    if !expired_keys.is_empty() {
+        message.push_str(&format!(
+            ", without expired taints: {}",
+            expired_keys.join(", ")
+        ));
+    }
+    info!(node = %node_name, action = "restore_snapshot", dry_run, "{}", message);
+    if !dry_run {
+        emit_event(ctx, &node, "SnapshotRestored", &message, EventType::Normal).await;
+    }
+    Ok(taints)
+}
@@ -1,37 +1,87 @@
+pub mod breaker;
+pub mod config;
+pub mod crypto;
+pub mod drift;
+pub mod events;
+pub mod expiry;
+pub mod history;
+pub mod logging;
+pub mod storage;
+pub mod telemetry;
+pub mod template;
+pub mod uninstall;
+
+use breaker::CircuitBreaker;
+pub use config::{
+    Config, ConfigOverrides, LogFormat, NodeSelectionMode, RecordCompression, RestoreTrigger,
+    StorageLayout,
+};
+use crypto::Keyring;
+use events::EventRecorder;
+use expiry::Expiry;
+use logging::LogControl;
+use storage::{get_record, record_revision, store_record, CaptureReason};
+
+use futures::{FutureExt, Stream};
 use k8s_openapi::{
-    api::core::v1::{ConfigMap, Event, Node, ObjectReference, Taint},
-    apimachinery::pkg::apis::meta::v1::{ObjectMeta, Time},
+    api::core::v1::{ConfigMap, Node, ObjectReference, Taint},
+    apimachinery::pkg::apis::meta::v1::Time,
+    chrono::{DateTime, Utc},
 };
 use kube::{
-    api::{Api, Patch, PatchParams, PostParams, ResourceExt},
+    api::{Api, ListParams, Patch, PatchParams, ResourceExt},
     error::ErrorResponse,
     runtime::{
         controller::Action,
+        events::EventType,
         finalizer::{finalizer, Event as FinalizerEvent},
+        reflector::{self, ObjectRef, Store},
+        watcher, WatchStreamExt,
     },
-    Client,
+    Client, Resource,
 };
 use lazy_static::lazy_static;
-use prometheus::{IntCounterVec, Opts, Registry};
+use prometheus::{
+    proto::{LabelPair, MetricFamily},
+    HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
 use sha2::{Digest, Sha256};
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    net::SocketAddr,
     sync::{
-        atomic::{AtomicU32, Ordering},
-        Arc,
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc, Mutex, RwLock,
     },
     time::{Duration, SystemTime},
 };
 use thiserror::Error;
-use tracing::{debug, error, info, warn};
+use tracing::{debug, error, field, info, info_span, warn, Instrument, Span};
 
 const FINALIZER_NAME: &str = "nodetaintpreserver.example.com/finalizer";
 const SERVICE_NAME: &str = "node-taint-preserver";
 const JSON_STORAGE_KEY: &str = "preserved_taints_json";
 const RESTORED_ANNOTATION_KEY: &str = "nodetaintpreserver.example.com/taints-restored";
+// Restored annotation value written before record revisions were tracked
+const LEGACY_RESTORED_VALUE: &str = "1";
+const NO_RECORD_REVISION: &str = "none";
+// Expiry times of taints on a node, as comma separated key=<RFC 3339 time>
+const TAINT_EXPIRY_ANNOTATION: &str = "nodetaintpreserver.example.com/taint-expiry";
+const ENFORCED_RECORD_ANNOTATION: &str = "nodetaintpreserver.example.com/enforced";
 const CONFIGMAP_NODE_ANNOTATION: &str = "nodetaintpreserver.example.com/node-name";
-const REQUEUE_TIME: Duration = Duration::from_secs(2);
-const MAX_RETRY_TIME: Duration = Duration::from_secs(3600);
+const MANAGED_BY_LABEL: &str = "app.kubernetes.io/managed-by";
+const COMPONENT_LABEL: &str = "app.kubernetes.io/component";
+const RECORD_COMPONENT: &str = "taint-record";
+// Hash of the node name, as node names may be longer than a label value
+const NODE_HASH_LABEL: &str = "nodetaintpreserver.example.com/node-hash";
+// Taint holding new nodes until their stored taints are restored
+const RESTORE_GATE_TAINT_KEY: &str = "nodetaintpreserver.example.com/restore-pending";
+const SKIP_NODE_KEY: &str = "nodetaintpreserver.example.com/skip";
+const MANAGED_NODE_KEY: &str = "nodetaintpreserver.example.com/managed";
+// How soon restores waiting for the record cache to sync are retried
+const RECORD_CACHE_RECHECK: Duration = Duration::from_secs(2);
 
 // Protected taint prefixes that should never be stored or restored
 const PROTECTED_TAINT_PREFIXES: &[&str] = &[
@@ -39,7 +89,7 @@ const PROTECTED_TAINT_PREFIXES: &[&str] = &[
     "node.cloudprovider.kubernetes.io/",
     "node-role.kubernetes.io/",
 ];
-const PROTECTED_TAINT_KEYS: &[&str] = &["CriticalAddonsOnly"];
+const PROTECTED_TAINT_KEYS: &[&str] = &["CriticalAddonsOnly", RESTORE_GATE_TAINT_KEY];
 
 lazy_static! {
     pub static ref PROMETHEUS_REGISTRY: Registry = Registry::new();
@@ -48,16 +98,109 @@ lazy_static! {
         &["node", "key"]
     )
     .unwrap();
+    static ref TAINTS_CAPTURED_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("taints_captured_total", "Total number of taints stored on node deletion"),
+        &["node", "key"]
+    )
+    .unwrap();
+    static ref TAINTS_EXPIRED_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("taints_expired_total", "Total number of expired taints removed from nodes"),
+        &["node", "key"]
+    )
+    .unwrap();
     static ref NODES_RECONCILED_TOTAL: IntCounterVec = IntCounterVec::new(
         Opts::new("nodes_reconciled_total", "Total number of nodes reconciled"),
         &["phase"]
     )
     .unwrap();
+    static ref RECONCILE_DURATION_SECONDS: HistogramVec = HistogramVec::new(
+        HistogramOpts::new("reconcile_duration_seconds", "Duration of node reconciles"),
+        &["phase"]
+    )
+    .unwrap();
+    static ref API_CALL_DURATION_SECONDS: HistogramVec = HistogramVec::new(
+        HistogramOpts::new(
+            "api_call_duration_seconds",
+            "Latency of Kubernetes API calls made by the controller"
+        ),
+        &["resource", "verb"]
+    )
+    .unwrap();
     static ref ERRORS_TOTAL: IntCounterVec = IntCounterVec::new(
         Opts::new("errors_total", "Total number of errors"),
         &["kind", "reason"]
     )
     .unwrap();
+    static ref FINALIZER_TIMEOUTS_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "finalizer_timeouts_total",
+            "Total number of node cleanups that timed out, by whether a fallback snapshot was stored"
+        ),
+        &["node", "outcome"]
+    )
+    .unwrap();
+    static ref CIRCUIT_BREAKER_OPEN: IntGauge = IntGauge::new(
+        "circuit_breaker_open",
+        "Whether taint restores are paused after a mass node deletion"
+    )
+    .unwrap();
+    static ref CIRCUIT_BREAKER_TRIPS_TOTAL: IntCounter = IntCounter::new(
+        "circuit_breaker_trips_total",
+        "Total number of mass node deletions detected"
+    )
+    .unwrap();
+    static ref EVENTS_SUPPRESSED_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "events_suppressed_total",
+            "Total number of Events dropped by the per-object rate limit"
+        ),
+        &["reason"]
+    )
+    .unwrap();
+    static ref RECORD_CACHE_LOOKUPS_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "record_cache_lookups_total",
+            "Total number of record lookups, by whether the record cache answered them"
+        ),
+        &["result"]
+    )
+    .unwrap();
+    static ref RECORDS_TRUNCATED_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "records_truncated_total",
+            "Total number of records stored with taints dropped to fit the size limit"
+        ),
+        &["node"]
+    )
+    .unwrap();
+    static ref STORED_RECORDS: IntGauge = IntGauge::new(
+        "stored_records",
+        "Number of node records stored as ConfigMaps"
+    )
+    .unwrap();
+    static ref MANAGED_NODES: IntGauge = IntGauge::new(
+        "managed_nodes",
+        "Number of live nodes managed by the controller"
+    )
+    .unwrap();
+    static ref NODES_AWAITING_RESTORE: IntGauge = IntGauge::new(
+        "nodes_awaiting_restore",
+        "Number of managed nodes whose stored record has not been restored yet"
+    )
+    .unwrap();
+    static ref NODES_WITH_DRIFT: IntGauge = IntGauge::new(
+        "nodes_with_drift",
+        "Number of nodes whose custom taints differ from their stored record"
+    )
+    .unwrap();
+    static ref NODE_TAINT_DRIFT: IntGaugeVec = IntGaugeVec::new(
+        Opts::new(
+            "node_taint_drift",
+            "Number of taints differing between a node and its stored record"
+        ),
+        &["node", "kind"]
+    )
+    .unwrap();
 }
 
 /// Initialize Prometheus metrics
@@ -71,6 +214,114 @@ pub fn init_metrics() {
     PROMETHEUS_REGISTRY
         .register(Box::new(ERRORS_TOTAL.clone()))
         .ok();
+    PROMETHEUS_REGISTRY
+        .register(Box::new(FINALIZER_TIMEOUTS_TOTAL.clone()))
+        .ok();
+    PROMETHEUS_REGISTRY
+        .register(Box::new(CIRCUIT_BREAKER_OPEN.clone()))
+        .ok();
+    PROMETHEUS_REGISTRY
+        .register(Box::new(CIRCUIT_BREAKER_TRIPS_TOTAL.clone()))
+        .ok();
+    PROMETHEUS_REGISTRY
+        .register(Box::new(EVENTS_SUPPRESSED_TOTAL.clone()))
+        .ok();
+    PROMETHEUS_REGISTRY
+        .register(Box::new(TAINTS_CAPTURED_TOTAL.clone()))
+        .ok();
+    PROMETHEUS_REGISTRY
+        .register(Box::new(TAINTS_EXPIRED_TOTAL.clone()))
+        .ok();
+    PROMETHEUS_REGISTRY
+        .register(Box::new(RECONCILE_DURATION_SECONDS.clone()))
+        .ok();
+    PROMETHEUS_REGISTRY
+        .register(Box::new(API_CALL_DURATION_SECONDS.clone()))
+        .ok();
+    PROMETHEUS_REGISTRY
+        .register(Box::new(RECORD_CACHE_LOOKUPS_TOTAL.clone()))
+        .ok();
+    PROMETHEUS_REGISTRY
+        .register(Box::new(RECORDS_TRUNCATED_TOTAL.clone()))
+        .ok();
+    PROMETHEUS_REGISTRY
+        .register(Box::new(STORED_RECORDS.clone()))
+        .ok();
+    PROMETHEUS_REGISTRY
+        .register(Box::new(MANAGED_NODES.clone()))
+        .ok();
+    PROMETHEUS_REGISTRY
+        .register(Box::new(NODES_AWAITING_RESTORE.clone()))
+        .ok();
+    PROMETHEUS_REGISTRY
+        .register(Box::new(NODES_WITH_DRIFT.clone()))
+        .ok();
+    PROMETHEUS_REGISTRY
+        .register(Box::new(NODE_TAINT_DRIFT.clone()))
+        .ok();
+}
+
+/// Time a Kubernetes API call made by the controller
+pub(crate) async fn observe_api_call<T>(
+    resource: &str,
+    verb: &str,
+    call: impl Future<Output = T>,
+) -> T {
+    let _timer = API_CALL_DURATION_SECONDS
+        .with_label_values(&[resource, verb])
+        .start_timer();
+    call.instrument(info_span!("api_call", resource, verb))
+        .await
+}
+
+/// Value of the `node` metric label, empty when per-node labels are disabled,
+/// which omits the label from the gathered series
+fn node_label<'a>(config: &Config, node_name: &'a str) -> &'a str {
+    if config.per_node_metrics {
+        node_name
+    } else {
+        ""
+    }
+}
+
+/// Serve the Prometheus metrics over HTTP on `/metrics`
+pub async fn serve_metrics(addr: SocketAddr) -> std::io::Result<()> {
+    let app = axum::Router::new().route("/metrics", axum::routing::get(render_metrics));
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    axum::serve(listener, app).await
+}
+
+/// Serve the admin endpoints over HTTP, the log level on `/log-level`.
+/// They are unauthenticated, so `addr` should not be reachable from outside
+/// the pod.
+pub async fn serve_admin(addr: SocketAddr, log_control: Arc<LogControl>) -> std::io::Result<()> {
+    let app = logging::log_level_routes(log_control);
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    axum::serve(listener, app).await
+}
+
+async fn render_metrics() -> String {
+    TextEncoder::new()
+        .encode_to_string(&gather_metrics())
+        .unwrap_or_default()
+}
+
+/// Gather the registered metrics, omitting the `node` label of series
+/// recorded without per-node labels
+pub fn gather_metrics() -> Vec<MetricFamily> {
+    let mut families = PROMETHEUS_REGISTRY.gather();
+    for family in &mut families {
+        for metric in family.mut_metric().iter_mut() {
+            let labels: Vec<LabelPair> = metric
+                .get_label()
+                .iter()
+                .filter(|label| label.get_name() != "node" || !label.get_value().is_empty())
+                .cloned()
+                .collect();
+            metric.set_label(labels.into());
+        }
+    }
+    families
 }
 
 #[derive(Debug, Error)]
@@ -83,62 +334,363 @@ pub enum Error {
     Serialization(#[from] serde_json::Error),
     #[error("Finalizer error: {0}")]
     Finalizer(String),
+    #[error("Record shard {0} is full, increase shard_prefix_length to migrate records into more shards")]
+    ShardFull(String),
+    #[error("Record compression error: {0}")]
+    Compression(#[source] std::io::Error),
+    #[error("Record encryption error: {0}")]
+    Encryption(String),
+    #[error("Node {0} has no snapshot {1}")]
+    SnapshotNotFound(String, usize),
+    #[error("Taint value templates failed for node {0}: {1}")]
+    TemplateFailed(String, String),
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
+/// Last observed state of a watched record
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct RecordState {
+    revision: String,
+    enforced: bool,
+}
+
 /// Passed to the reconciler
 pub struct Context {
     client: Client,
-    configmap_namespace: String,
-    extra_protected_prefixes: Vec<String>,
+    config: RwLock<Arc<Config>>,
+    records: RwLock<HashMap<String, RecordState>>,
+    record_cache: RwLock<Store<ConfigMap>>,
+    record_watch_started: AtomicBool,
+    // resourceVersions of record ConfigMaps written since the cache saw them
+    written_records: Mutex<HashMap<String, String>>,
+    keyring: Keyring,
+    last_known_taints: Mutex<HashMap<String, Vec<Taint>>>,
+    in_flight: Mutex<BTreeMap<String, &'static str>>,
+    breaker: CircuitBreaker,
+    recorder: EventRecorder,
     attempt: AtomicU32,
 }
 
 impl Context {
     /// Create a new Context
-    pub fn new(client: Client) -> Self {
-        let configmap_namespace =
-            std::env::var("CONFIGMAP_NAMESPACE").unwrap_or_else(|_| "default".to_string());
-        let extra_protected_prefixes = std::env::var("EXTRA_PROTECTED_TAINT_PREFIXES")
-            .unwrap_or_default()
-            .split(',')
-            .filter(|s| !s.is_empty())
-            .map(|s| s.trim().to_string())
-            .collect();
-
+    pub fn new(client: Client, config: Config) -> Self {
         init_metrics();
 
         Self {
+            recorder: EventRecorder::new(client.clone()),
             client,
-            configmap_namespace,
-            extra_protected_prefixes,
+            config: RwLock::new(Arc::new(config)),
+            records: RwLock::new(HashMap::new()),
+            record_cache: RwLock::new(reflector::store().0),
+            record_watch_started: AtomicBool::new(false),
+            written_records: Mutex::new(HashMap::new()),
+            keyring: Keyring::default(),
+            last_known_taints: Mutex::new(HashMap::new()),
+            in_flight: Mutex::new(BTreeMap::new()),
+            breaker: CircuitBreaker::default(),
             attempt: AtomicU32::new(0),
         }
     }
 
-    fn cm_api(&self) -> Api<ConfigMap> {
-        Api::<ConfigMap>::namespaced(self.client.clone(), &self.configmap_namespace)
+    /// Encrypt records with the keys of a keyring
+    pub fn with_keyring(mut self, keyring: Keyring) -> Self {
+        self.keyring = keyring;
+        self
+    }
+
+    /// Keys records are encrypted with
+    pub(crate) fn keyring(&self) -> &Keyring {
+        &self.keyring
+    }
+
+    /// Current configuration
+    pub fn config(&self) -> Arc<Config> {
+        self.config.read().unwrap().clone()
+    }
+
+    /// Replace the configuration of the running controller
+    pub fn set_config(&self, config: Config) {
+        *self.config.write().unwrap() = Arc::new(config);
+    }
+
+    /// Watcher configuration restricted to the configured node selectors
+    pub fn node_watcher_config(&self) -> watcher::Config {
+        let config = self.config();
+        let mut watcher_config = watcher::Config::default();
+        if let Some(labels) = &config.node_label_selector {
+            watcher_config = watcher_config.labels(labels);
+        }
+        if let Some(fields) = &config.node_field_selector {
+            watcher_config = watcher_config.fields(fields);
+        }
+        watcher_config
+    }
+
+    /// List parameters restricted to the configured node selectors,
+    /// for any operation that lists nodes outside of the watcher
+    pub fn node_list_params(&self) -> ListParams {
+        let config = self.config();
+        let mut params = ListParams::default();
+        if let Some(labels) = &config.node_label_selector {
+            params = params.labels(labels);
+        }
+        if let Some(fields) = &config.node_field_selector {
+            params = params.fields(fields);
+        }
+        params
+    }
+
+    /// Human readable description of which nodes the controller manages
+    pub fn describe_scope(&self) -> String {
+        let config = self.config();
+        format!(
+            "labels '{}', fields '{}', selection mode {:?}",
+            config.node_label_selector.as_deref().unwrap_or("<all>"),
+            config.node_field_selector.as_deref().unwrap_or("<all>"),
+            config.node_selection_mode
+        )
+    }
+
+    /// API for the ConfigMaps holding the preserved state
+    pub fn cm_api(&self) -> Api<ConfigMap> {
+        Api::<ConfigMap>::namespaced(self.client.clone(), &self.config().configmap_namespace)
     }
+
+    /// Watch the record ConfigMaps, keeping the record cache up to date.
+    /// Each call starts a new cache, which is read once the stream has
+    /// listed all records.
+    pub fn watch_records(&self) -> impl Stream<Item = Result<ConfigMap, watcher::Error>> {
+        let (reader, writer) = reflector::store();
+        *self.record_cache.write().unwrap() = reader;
+        self.record_watch_started.store(true, Ordering::SeqCst);
+        let watcher_config = watcher::Config::default().labels(&record_selector());
+        reflector::reflector(writer, watcher(self.cm_api(), watcher_config))
+            .default_backoff()
+            .touched_objects()
+    }
+
+    /// Whether the record cache has listed all records
+    pub fn record_cache_synced(&self) -> bool {
+        let cache = self.record_cache.read().unwrap().clone();
+        matches!(cache.wait_until_ready().now_or_never(), Some(Ok(())))
+    }
+
+    /// Whether the record watch was started but has not listed all records
+    /// yet, so record lookups would fall back to live GETs
+    pub fn record_cache_pending(&self) -> bool {
+        self.record_watch_started.load(Ordering::SeqCst) && !self.record_cache_synced()
+    }
+
+    /// Record ConfigMap from the record cache, `Some(None)` when the synced
+    /// cache does not hold it. `None` when the cache cannot tell: it has not
+    /// synced yet or has not seen the last write of the ConfigMap.
+    fn cached_configmap(&self, name: &str) -> Option<Option<Arc<ConfigMap>>> {
+        if !self.record_cache_synced() {
+            return None;
+        }
+        let key = ObjectRef::new(name).within(&self.config().configmap_namespace);
+        let cm = self.record_cache.read().unwrap().get(&key);
+        let mut written = self.written_records.lock().unwrap();
+        match written.get(name) {
+            Some(version)
+                if cm
+                    .as_ref()
+                    .and_then(|cm| cm.metadata.resource_version.as_ref())
+                    != Some(version) =>
+            {
+                None
+            }
+            Some(_) => {
+                written.remove(name);
+                Some(cm)
+            }
+            None => Some(cm),
+        }
+    }
+
+    /// Read a record ConfigMap written by this process live until the
+    /// record cache saw the write
+    fn record_written(&self, cm: &ConfigMap) {
+        if let Some(version) = cm.resource_version() {
+            self.written_records
+                .lock()
+                .unwrap()
+                .insert(cm.name_any(), version);
+        }
+    }
+
+    /// Track the state of the records in a watched ConfigMap, returning
+    /// the nodes to reconcile because their record is new or has changed
+    pub fn observe_record(&self, cm: &ConfigMap) -> Vec<ObjectRef<Node>> {
+        {
+            let mut written = self.written_records.lock().unwrap();
+            if written.get(&cm.name_any()) == cm.metadata.resource_version.as_ref() {
+                written.remove(&cm.name_any());
+            }
+        }
+        let mut records = self.records.write().unwrap();
+        storage::records_in(&self.keyring, cm)
+            .into_iter()
+            .filter_map(|record| {
+                let state = RecordState {
+                    revision: record.revision,
+                    enforced: record.enforced,
+                };
+                let previous = records.insert(record.node.clone(), state.clone());
+                (previous.as_ref() != Some(&state)).then(|| ObjectRef::new(&record.node))
+            })
+            .collect()
+    }
+
+    /// Nodes currently being reconciled, with the phase they are in
+    pub fn in_flight(&self) -> Vec<(String, &'static str)> {
+        self.in_flight
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(node_name, phase)| (node_name.clone(), *phase))
+            .collect()
+    }
+
+    /// Mark a node as being reconciled until the returned guard is dropped
+    fn track_in_flight(&self, node_name: &str) -> InFlightGuard<'_> {
+        self.set_phase(node_name, "reconcile");
+        InFlightGuard {
+            ctx: self,
+            node_name: node_name.to_string(),
+        }
+    }
+
+    fn set_phase(&self, node_name: &str, phase: &'static str) {
+        self.in_flight
+            .lock()
+            .unwrap()
+            .insert(node_name.to_string(), phase);
+    }
+
+    /// Remember the custom taints of a node once they were applied, as a
+    /// fallback snapshot should its cleanup time out
+    fn remember_taints(&self, node_name: &str, taints: &[Taint]) {
+        self.last_known_taints
+            .lock()
+            .unwrap()
+            .insert(node_name.to_string(), taints.to_vec());
+    }
+
+    /// Remember the custom taints of a node whose taints were never seen
+    /// applied, e.g. one first seen by this process while being deleted
+    fn remember_unknown_taints(&self, node_name: &str, taints: &[Taint]) {
+        self.last_known_taints
+            .lock()
+            .unwrap()
+            .entry(node_name.to_string())
+            .or_insert_with(|| taints.to_vec());
+    }
+
+    fn last_known_taints(&self, node_name: &str) -> Option<Vec<Taint>> {
+        self.last_known_taints
+            .lock()
+            .unwrap()
+            .get(node_name)
+            .cloned()
+    }
+
+    fn forget_taints(&self, node_name: &str) {
+        self.last_known_taints.lock().unwrap().remove(node_name);
+    }
+
+    /// Forget the taints of nodes that are gone, e.g. force deleted
+    /// without their cleanup
+    pub(crate) fn retain_known_taints(&self, nodes: &[Node]) {
+        self.last_known_taints
+            .lock()
+            .unwrap()
+            .retain(|node_name, _| nodes.iter().any(|node| node.name_any() == *node_name));
+    }
+
+    /// Check whether the watched record of a node is enforced as desired state
+    fn record_enforced(&self, node_name: &str) -> bool {
+        self.config().enforce_records
+            && self
+                .records
+                .read()
+                .unwrap()
+                .get(node_name)
+                .is_some_and(|state| state.enforced)
+    }
+
+    /// Check whether the watched record of a node differs from the
+    /// revision that was last restored onto it
+    fn record_changed_since(&self, node_name: &str, restored_revision: &str) -> bool {
+        if restored_revision == LEGACY_RESTORED_VALUE {
+            return false;
+        }
+        self.records
+            .read()
+            .unwrap()
+            .get(node_name)
+            .is_some_and(|state| state.revision != restored_revision)
+    }
+}
+
+/// Removes a node from the in-flight reconciles when dropped
+struct InFlightGuard<'a> {
+    ctx: &'a Context,
+    node_name: String,
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.ctx.in_flight.lock().unwrap().remove(&self.node_name);
+    }
+}
+
+/// Hex encoded SHA-256 hash of a node name
+fn node_hash(node_name: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(node_name.as_bytes());
+    hex::encode(hasher.finalize())
 }
 
 /// Generates the expected ConfigMap name for a given node name.
 /// We hash the node name to a fixed length to ensure our ConfigMap
 /// name is not longer than Kubernetes' key character limit.
 fn configmap_name(node_name: &str) -> String {
-    let mut hasher = Sha256::new();
-    hasher.update(node_name.as_bytes());
-    let full_hash = hasher.finalize();
-    let hex_encoded_hash = hex::encode(full_hash);
-    format!("node-taints-{}", hex_encoded_hash)
+    format!("node-taints-{}", node_hash(node_name))
+}
+
+/// Standard labels of the record of a node
+fn record_labels(node_name: &str) -> BTreeMap<String, String> {
+    // Label values are limited to 63 characters
+    let mut hash = node_hash(node_name);
+    hash.truncate(63);
+    BTreeMap::from([
+        (MANAGED_BY_LABEL.to_string(), SERVICE_NAME.to_string()),
+        (COMPONENT_LABEL.to_string(), RECORD_COMPONENT.to_string()),
+        (NODE_HASH_LABEL.to_string(), hash),
+    ])
+}
+
+/// Label selector matching every record
+fn record_selector() -> String {
+    format!(
+        "{}={},{}={}",
+        MANAGED_BY_LABEL, SERVICE_NAME, COMPONENT_LABEL, RECORD_COMPONENT
+    )
+}
+
+/// List parameters selecting every record
+pub(crate) fn record_list_params() -> ListParams {
+    ListParams::default().labels(&record_selector())
 }
 
 /// Check if a taint is protected and should not be stored/restored
-fn is_taint_protected(taint: &Taint, extra_prefixes: &[String]) -> bool {
+fn is_taint_protected(taint: &Taint, config: &Config) -> bool {
     let key = &taint.key;
 
     // Check against protected keys
-    if PROTECTED_TAINT_KEYS.contains(&key.as_str()) {
+    if PROTECTED_TAINT_KEYS.contains(&key.as_str()) || config.extra_protected_keys.contains(key) {
         return true;
     }
 
@@ -150,7 +702,7 @@ fn is_taint_protected(taint: &Taint, extra_prefixes: &[String]) -> bool {
     }
 
     // Check against extra protected prefixes
-    for prefix in extra_prefixes {
+    for prefix in &config.extra_protected_prefixes {
         if key.starts_with(prefix) {
             return true;
         }
@@ -160,15 +712,83 @@ fn is_taint_protected(taint: &Taint, extra_prefixes: &[String]) -> bool {
 }
 
 /// Filter out protected taints from a list
-fn filter_protected_taints(taints: Vec<Taint>, extra_prefixes: &[String]) -> Vec<Taint> {
+fn filter_protected_taints(taints: Vec<Taint>, config: &Config) -> Vec<Taint> {
     taints
         .into_iter()
-        .filter(|t| !is_taint_protected(t, extra_prefixes))
+        .filter(|t| !is_taint_protected(t, config))
         .collect()
 }
 
+/// Custom taints of a node, without the protected ones
+fn custom_taints(node: &Node, config: &Config) -> Vec<Taint> {
+    let taints = node
+        .spec
+        .as_ref()
+        .and_then(|spec| spec.taints.clone())
+        .unwrap_or_default();
+    filter_protected_taints(taints, config)
+}
+
+/// Check if a node carries a taint with the given key
+fn live_taints_have_key(node: &Node, key: &str) -> bool {
+    node.spec
+        .as_ref()
+        .and_then(|spec| spec.taints.as_ref())
+        .is_some_and(|taints| taints.iter().any(|t| t.key == key))
+}
+
+/// Check whether a node label or annotation is set to "true"
+fn has_node_flag(node: &Node, key: &str) -> bool {
+    [node.labels(), node.annotations()]
+        .iter()
+        .any(|map| map.get(key).is_some_and(|v| v == "true"))
+}
+
+/// Check if a node should be managed by the controller
+fn is_node_managed(node: &Node, mode: NodeSelectionMode) -> bool {
+    if has_node_flag(node, SKIP_NODE_KEY) {
+        return false;
+    }
+
+    match mode {
+        NodeSelectionMode::OptOut => true,
+        NodeSelectionMode::OptIn => has_node_flag(node, MANAGED_NODE_KEY),
+    }
+}
+
+/// Check if the kubelet reports the node as Ready
+fn is_node_ready(node: &Node) -> bool {
+    node.status
+        .as_ref()
+        .and_then(|status| status.conditions.as_ref())
+        .is_some_and(|conditions| {
+            conditions
+                .iter()
+                .any(|c| c.type_ == "Ready" && c.status == "True")
+        })
+}
+
 /// Action to take on Node events
 pub async fn reconcile(node: Arc<Node>, ctx: Arc<Context>) -> Result<Action> {
+    let span = info_span!(
+        "reconcile",
+        node = %node.name_any(),
+        uid = node.uid().as_deref(),
+        outcome = field::Empty,
+        trace_id = field::Empty
+    );
+    span.record("trace_id", telemetry::trace_id(&span));
+    traced(span, reconcile_node(node, ctx)).await
+}
+
+/// Run a reconcile step within a span, recording whether it succeeded
+async fn traced(span: Span, step: impl Future<Output = Result<Action>>) -> Result<Action> {
+    let result = step.instrument(span.clone()).await;
+    span.record("outcome", if result.is_ok() { "success" } else { "error" });
+    result
+}
+
+async fn reconcile_node(node: Arc<Node>, ctx: Arc<Context>) -> Result<Action> {
     let node_name = node
         .metadata
         .name
@@ -176,16 +796,40 @@ pub async fn reconcile(node: Arc<Node>, ctx: Arc<Context>) -> Result<Action> {
         .ok_or_else(|| Error::MissingNodeName(Box::new(node.as_ref().clone())))?
         .to_string();
     let node_api: Api<Node> = Api::all(ctx.client.clone());
+    let _in_flight = ctx.track_in_flight(&node_name);
+
+    let managed = is_node_managed(&node, ctx.config().node_selection_mode);
+    let phase = if !managed {
+        "release"
+    } else if node.metadata.deletion_timestamp.is_some() {
+        "cleanup"
+    } else {
+        "apply"
+    };
+    let _timer = RECONCILE_DURATION_SECONDS
+        .with_label_values(&[phase])
+        .start_timer();
+
+    // Unmanaged nodes never get a record, only our finalizer removed
+    if !managed {
+        return release_node(&node, &ctx).await;
+    }
 
     finalizer(&node_api, FINALIZER_NAME, node, |event| async {
         match event {
-            FinalizerEvent::Apply(node) => apply_node(node, ctx.clone()).await,
-            FinalizerEvent::Cleanup(node) => cleanup_node(node, ctx.clone()).await,
+            FinalizerEvent::Apply(node) => {
+                let span = info_span!("apply_node", node = %node_name, outcome = field::Empty);
+                traced(span, apply_node(node, ctx.clone())).await
+            }
+            FinalizerEvent::Cleanup(node) => {
+                let span = info_span!("cleanup_node", node = %node_name, outcome = field::Empty);
+                traced(span, cleanup_node(node, ctx.clone())).await
+            }
         }
     })
     .await
     .map_err(|e| {
-        warn!("Finalizer error for node {}: {:?}", node_name, e);
+        warn!(node = %node_name, error = ?e, "Finalizer error");
         ERRORS_TOTAL
             .with_label_values(&["finalizer", "finalizer_error"])
             .inc();
@@ -193,16 +837,198 @@ pub async fn reconcile(node: Arc<Node>, ctx: Arc<Context>) -> Result<Action> {
     })
 }
 
+// Attempts at releasing a node whose finalizers keep changing under us
+const MAX_RELEASE_ATTEMPTS: usize = 5;
+
+/// Remove our finalizer, and optionally our restore annotation, from a node,
+/// re-reading it when its metadata changed concurrently. Returns whether
+/// there was anything to remove.
+pub(crate) async fn remove_finalizer(
+    node_api: &Api<Node>,
+    mut node: Node,
+    patch_params: &PatchParams,
+    remove_annotation: bool,
+) -> std::result::Result<bool, kube::Error> {
+    let node_name = node.name_any();
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let has_finalizer = node.finalizers().iter().any(|f| f == FINALIZER_NAME);
+        let has_annotation =
+            remove_annotation && node.annotations().contains_key(RESTORED_ANNOTATION_KEY);
+        if !has_finalizer && !has_annotation {
+            return Ok(false);
+        }
+
+        let remaining: Vec<&String> = node
+            .finalizers()
+            .iter()
+            .filter(|f| *f != FINALIZER_NAME)
+            .collect();
+
+        // resourceVersion makes the merge patch fail on concurrent finalizer edits
+        let mut patch_payload = serde_json::json!({
+            "metadata": {
+                "resourceVersion": node.resource_version(),
+                "finalizers": remaining
+            }
+        });
+        if remove_annotation {
+            patch_payload["metadata"]["annotations"] =
+                serde_json::json!({ RESTORED_ANNOTATION_KEY: null });
+        }
+
+        let patched = observe_api_call(
+            "nodes",
+            "patch",
+            node_api.patch(&node_name, patch_params, &Patch::Merge(&patch_payload)),
+        )
+        .await;
+        match patched {
+            Ok(_) => return Ok(true),
+            Err(kube::Error::Api(ErrorResponse { code: 409, .. }))
+                if attempt < MAX_RELEASE_ATTEMPTS =>
+            {
+                debug!(node = %node_name, attempt, "Node changed concurrently, re-reading it");
+                match observe_api_call("nodes", "get", node_api.get_opt(&node_name)).await? {
+                    Some(latest) => node = latest,
+                    None => return Ok(false),
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Remove our finalizer from a node that is not managed
+async fn release_node(node: &Node, ctx: &Context) -> Result<Action> {
+    let node_name = node.name_any();
+    let node_api: Api<Node> = Api::all(ctx.client.clone());
+    let released = remove_finalizer(&node_api, node.clone(), &PatchParams::default(), false)
+        .await
+        .map_err(|e| {
+            ERRORS_TOTAL
+                .with_label_values(&["node", "release_error"])
+                .inc();
+            Error::Kube(e)
+        })?;
+    if !released {
+        return Ok(Action::await_change());
+    }
+
+    ctx.forget_taints(&node_name);
+    info!(
+        node = %node_name,
+        phase = "release",
+        action = "remove_finalizer",
+        "Node is not managed, removed finalizer"
+    );
+    NODES_RECONCILED_TOTAL.with_label_values(&["release"]).inc();
+
+    Ok(Action::await_change())
+}
+
+/// A stored taint prepared to be put back on a node
+pub(crate) enum PreparedTaint {
+    /// The taint, with its value rendered from its key's template
+    Ready(Taint),
+    /// The taint's expiry time has passed
+    Expired,
+    /// The value template of the taint's key failed for the node
+    Failed(template::TemplateError),
+}
+
+/// Prepare a stored taint to be put back on a node, as restores and
+/// snapshot restores do
+pub(crate) fn prepare_taint(
+    taint: Taint,
+    expires_at: Option<&Time>,
+    templates: &BTreeMap<String, String>,
+    node: &Node,
+    now: DateTime<Utc>,
+) -> PreparedTaint {
+    if expiry::is_expired(expires_at, now) {
+        return PreparedTaint::Expired;
+    }
+    match template::render_taint(taint, templates, node) {
+        Ok(taint) => PreparedTaint::Ready(taint),
+        Err(e) => PreparedTaint::Failed(e),
+    }
+}
+
 /// Handle Node Creation/Update
 async fn apply_node(node: Arc<Node>, ctx: Arc<Context>) -> Result<Action> {
     let node_name = node.name_any();
 
-    // Check if already processed (idempotence)
-    if node.annotations().contains_key(RESTORED_ANNOTATION_KEY) {
-        return Ok(Action::await_change());
+    // Expired taints are removed first, and the node reconciled again
+    let next_expiry = match expiry::expire_taints(&ctx, &node).await? {
+        Expiry::Patched => return Ok(Action::await_change()),
+        Expiry::Pending(next) => next,
+    };
+    // Nodes with taints that expire later are checked again by then
+    let idle = || next_expiry.map_or_else(Action::await_change, Action::requeue);
+
+    let restore_trigger = ctx.config().restore_trigger;
+    let gated = restore_trigger == RestoreTrigger::StartupTaint
+        && live_taints_have_key(&node, RESTORE_GATE_TAINT_KEY);
+
+    // Check if already processed (idempotence), unless the record changed since
+    // or is enforced, in which case missing taints are re-applied on every pass
+    let mut enforcement_pass = false;
+    if let Some(restored_revision) = node.annotations().get(RESTORED_ANNOTATION_KEY) {
+        if ctx.record_changed_since(&node_name, restored_revision) {
+            info!(
+                node = %node_name,
+                phase = "apply",
+                record = %configmap_name(&node_name),
+                "Record changed since it was restored, restoring again"
+            );
+        } else if gated {
+            info!(node = %node_name, phase = "apply", "Node carries the restore gate taint again");
+        } else if ctx.record_enforced(&node_name) {
+            enforcement_pass = true;
+        } else {
+            ctx.remember_taints(&node_name, &custom_taints(&node, &ctx.config()));
+            return Ok(idle());
+        }
+    }
+
+    // The kubelet and cloud controller manager may still rewrite taints until
+    // the node is Ready, and its status change triggers the next reconcile
+    if restore_trigger == RestoreTrigger::OnReady && !is_node_ready(&node) {
+        debug!(node = %node_name, phase = "apply", action = "delay", "Node is not Ready yet, delaying restore");
+        return Ok(idle());
+    }
+
+    // After a mass deletion, restores wait until an operator resumes them
+    if ctx.breaker.is_open() {
+        debug!(
+            node = %node_name,
+            phase = "apply",
+            action = "requeue",
+            "Circuit breaker is open, not restoring taints"
+        );
+        return Ok(Action::requeue(breaker::BREAKER_RECHECK));
+    }
+
+    // Until the record cache listed all records, each lookup would take
+    // several GETs, which a mass rollout multiplies by the number of nodes
+    if ctx.record_cache_pending() {
+        debug!(
+            node = %node_name,
+            phase = "apply",
+            action = "requeue",
+            "Record cache has not synced yet, delaying restore"
+        );
+        return Ok(Action::requeue(RECORD_CACHE_RECHECK));
     }
 
-    info!("Reconciling node '{}' (Apply)", node_name);
+    if enforcement_pass {
+        debug!(node = %node_name, phase = "apply", action = "enforce", "Enforcing record");
+    } else {
+        info!(node = %node_name, phase = "apply", "Reconciling node");
+    }
+    ctx.set_phase(&node_name, "apply");
     NODES_RECONCILED_TOTAL.with_label_values(&["apply"]).inc();
 
     let node_api: Api<Node> = Api::all(ctx.client.clone());
@@ -212,47 +1038,101 @@ async fn apply_node(node: Arc<Node>, ctx: Arc<Context>) -> Result<Action> {
         .and_then(|spec| spec.taints.clone())
         .unwrap_or_default();
 
-    let mut taints_to_restore: Vec<Taint> = Vec::new();
-
     // Check ConfigMap for preserved taints
-    let cm_name = configmap_name(&node_name);
-    match ctx.cm_api().get(&cm_name).await {
-        Ok(cm) => {
-            if let Some(data) = &cm.data {
-                if let Some(taints_json_str) = data.get(JSON_STORAGE_KEY) {
-                    taints_to_restore =
-                        serde_json::from_str(taints_json_str).map_err(Error::Serialization)?;
-                }
-            }
-        }
-        Err(kube::Error::Api(ErrorResponse { code: 404, .. })) => {
-            debug!("No ConfigMap found for node '{}'", node_name);
-        }
-        Err(e) => {
-            ERRORS_TOTAL
-                .with_label_values(&["configmap", "get_error"])
-                .inc();
-            return Err(Error::Kube(e));
-        }
-    }
+    let record = get_record(&ctx, &node_name).await?;
+    let revision = record_revision(record.as_ref());
+    let enforced = ctx.config().enforce_records && record.as_ref().is_some_and(|r| r.enforced);
+    let (taints_to_restore, record_expiry) =
+        record.map(|r| (r.taints, r.expiry)).unwrap_or_default();
+    let templates = ctx.config().taint_templates.clone();
+    // The expiry annotation only holds keys on the node, as expire_taints
+    // dropped the others, so missing taints expire at the stored times
+    let mut live_expiry = expiry::annotated_expiry(&node);
+    let now = Utc::now();
 
     // Merge taints: only add if key doesn't exist
-    let mut merged_taints = current_taints.clone();
+    let mut merged_taints: Vec<Taint> = current_taints;
     let mut restored_keys: Vec<String> = Vec::new();
+    let mut template_errors: Vec<String> = Vec::new();
+    let mut expired_keys: Vec<String> = Vec::new();
+    let mut expiry_restored = false;
 
     for taint in taints_to_restore {
         let exists = merged_taints.iter().any(|t| t.key == taint.key);
         if !exists {
+            let key = taint.key.clone();
+            let expires_at = record_expiry.get(&key).cloned();
+            let taint = match prepare_taint(taint, expires_at.as_ref(), &templates, &node, now) {
+                PreparedTaint::Ready(taint) => taint,
+                PreparedTaint::Expired => {
+                    expired_keys.push(key);
+                    continue;
+                }
+                PreparedTaint::Failed(e) => {
+                    template_errors.push(format!("{}: {}", key, e));
+                    continue;
+                }
+            };
+            if let Some(time) = expires_at {
+                expiry_restored |= live_expiry.insert(key, time.clone()) != Some(time);
+            }
             restored_keys.push(taint.key.clone());
             merged_taints.push(taint.clone());
             TAINTS_RESTORED_TOTAL
-                .with_label_values(&[&node_name, &taint.key])
+                .with_label_values(&[node_label(&ctx.config(), &node_name), &taint.key])
                 .inc();
         }
     }
 
-    // Only patch if we actually restored taints or need to add annotation
-    if !restored_keys.is_empty() || !node.annotations().contains_key(RESTORED_ANNOTATION_KEY) {
+    // Enforcement passes skip the same expired taints every time
+    if !expired_keys.is_empty() && !enforcement_pass {
+        let message = format!("Not restoring expired taints: {}", expired_keys.join(", "));
+        info!(node = %node_name, phase = "apply", action = "skip_expired", "{}", message);
+        emit_event(
+            &ctx,
+            &node,
+            "ExpiredTaintsSkipped",
+            &message,
+            EventType::Normal,
+        )
+        .await;
+    }
+
+    // A record with taints whose template failed is not marked as restored,
+    // so they are retried on the next change of the node, e.g. a new label
+    let restore_complete = template_errors.is_empty();
+    if !restore_complete {
+        let message = format!(
+            "Not restoring taints whose value template failed: {}",
+            template_errors.join(", ")
+        );
+        warn!(node = %node_name, phase = "apply", action = "skip_template", "{}", message);
+        ERRORS_TOTAL
+            .with_label_values(&["template", "render_error"])
+            .inc();
+        emit_event(
+            &ctx,
+            &node,
+            "TaintTemplateFailed",
+            &message,
+            EventType::Warning,
+        )
+        .await;
+    }
+
+    // The restore gate is removed in the same patch once every taint was
+    // restored, so the node is released atomically
+    let release_gate = gated && restore_complete;
+    if release_gate {
+        merged_taints.retain(|t| t.key != RESTORE_GATE_TAINT_KEY);
+    }
+
+    let applied_taints = filter_protected_taints(merged_taints.clone(), &ctx.config());
+
+    // Only patch if we actually restored taints or need to update the annotation
+    let annotation_outdated =
+        restore_complete && node.annotations().get(RESTORED_ANNOTATION_KEY) != Some(&revision);
+    if !restored_keys.is_empty() || annotation_outdated || release_gate {
         let mut node_spec = node.spec.clone().unwrap_or_default();
         node_spec.taints = if merged_taints.is_empty() {
             None
@@ -261,7 +1141,16 @@ async fn apply_node(node: Arc<Node>, ctx: Arc<Context>) -> Result<Action> {
         };
 
         let mut annotations = node.annotations().clone();
-        annotations.insert(RESTORED_ANNOTATION_KEY.to_string(), "1".to_string());
+        if restore_complete {
+            annotations.insert(RESTORED_ANNOTATION_KEY.to_string(), revision);
+        }
+        // Restored taints keep expiring at the time they had on the old node
+        if expiry_restored {
+            annotations.insert(
+                TAINT_EXPIRY_ANNOTATION.to_string(),
+                expiry::format_expiry(&live_expiry),
+            );
+        }
 
         let patch_payload = serde_json::json!({
             "metadata": {
@@ -273,13 +1162,34 @@ async fn apply_node(node: Arc<Node>, ctx: Arc<Context>) -> Result<Action> {
         });
 
         let patch_params = PatchParams::apply(SERVICE_NAME).force();
-        node_api
-            .patch(&node_name, &patch_params, &Patch::Apply(&patch_payload))
-            .await
-            .map_err(Error::Kube)?;
+        observe_api_call(
+            "nodes",
+            "patch",
+            node_api.patch(&node_name, &patch_params, &Patch::Apply(&patch_payload)),
+        )
+        .await
+        .map_err(Error::Kube)?;
 
         // Emit Kubernetes Event
-        if !restored_keys.is_empty() {
+        if enforcement_pass && !restored_keys.is_empty() {
+            let message = format!("Re-applied enforced taints: {}", restored_keys.join(", "));
+            emit_event(
+                &ctx,
+                &node,
+                "TaintDriftCorrected",
+                &message,
+                EventType::Warning,
+            )
+            .await;
+            warn!(
+                node = %node_name,
+                phase = "apply",
+                record = %configmap_name(&node_name),
+                action = "enforce",
+                "{}",
+                message
+            );
+        } else if !restored_keys.is_empty() {
             let message = if restored_keys.len() <= 5 {
                 format!("Restored taints: {}", restored_keys.join(", "))
             } else {
@@ -289,28 +1199,67 @@ async fn apply_node(node: Arc<Node>, ctx: Arc<Context>) -> Result<Action> {
                     restored_keys[..5].join(", ")
                 )
             };
-            emit_event(&ctx, &node_name, "TaintsRestored", &message, "Normal").await;
-            info!("Node '{}': {}", node_name, message);
-        } else {
+            emit_event(&ctx, &node, "TaintsRestored", &message, EventType::Normal).await;
+            info!(
+                node = %node_name,
+                phase = "apply",
+                record = %configmap_name(&node_name),
+                action = "restore",
+                "{}",
+                message
+            );
+        } else if annotation_outdated {
             emit_event(
                 &ctx,
-                &node_name,
+                &node,
                 "NoTaintsToRestore",
                 "No taints needed to be restored",
-                "Normal",
+                EventType::Normal,
             )
             .await;
         }
+        if release_gate {
+            info!(
+                node = %node_name,
+                phase = "apply",
+                action = "remove_gate",
+                "Removed restore gate taint"
+            );
+        }
     }
 
-    Ok(Action::await_change())
+    ctx.remember_taints(&node_name, &applied_taints);
+
+    // Enforced records are also re-checked periodically
+    if enforced {
+        let resync = ctx.config().enforcement_resync();
+        return Ok(Action::requeue(
+            next_expiry.map_or(resync, |next| next.min(resync)),
+        ));
+    }
+
+    Ok(idle())
 }
 
 /// Handle Node Deletion
 async fn cleanup_node(node: Arc<Node>, ctx: Arc<Context>) -> Result<Action> {
     let node_name = node.name_any();
-    info!("Cleaning up node '{}' (Cleanup)", node_name);
+    info!(node = %node_name, phase = "cleanup", "Cleaning up node");
+    ctx.set_phase(&node_name, "cleanup");
     NODES_RECONCILED_TOTAL.with_label_values(&["cleanup"]).inc();
+    let config = ctx.config();
+
+    // Get current taints
+    let all_taints = node
+        .spec
+        .as_ref()
+        .and_then(|spec| spec.taints.clone())
+        .unwrap_or_default();
+
+    // Filter out protected taints. Taints seen applied before win as the
+    // fallback snapshot, as they may have been stripped since.
+    let mut taints_to_preserve = filter_protected_taints(all_taints, &config);
+    ctx.remember_unknown_taints(&node_name, &taints_to_preserve);
 
     // Check if deletion has been pending for too long
     if let Some(Time(deletion_time)) = node.metadata.deletion_timestamp {
@@ -319,125 +1268,275 @@ async fn cleanup_node(node: Arc<Node>, ctx: Arc<Context>) -> Result<Action> {
         if current_time
             .duration_since(deletion_system_time)
             .unwrap_or_default()
-            > MAX_RETRY_TIME
+            > config.finalizer_timeout()
         {
-            warn!(
-                "Node '{}' termination cleanup failed for over {}s. Forcing finalizer removal.",
-                node_name,
-                MAX_RETRY_TIME.as_secs()
-            );
-            ERRORS_TOTAL
-                .with_label_values(&["cleanup", "timeout"])
-                .inc();
-            return Ok(Action::await_change());
+            return cleanup_timed_out(&node, &ctx).await;
         }
     }
 
-    // Get current taints
-    let all_taints = node
-        .spec
-        .as_ref()
-        .and_then(|spec| spec.taints.clone())
-        .unwrap_or_default();
-
-    // Filter out protected taints
-    let taints_to_preserve = filter_protected_taints(all_taints, &ctx.extra_protected_prefixes);
+    // Enforced records are desired state, so taints missing from the node are
+    // kept, with their expiry times unless the node has newer ones
+    let mut record_expiry = BTreeMap::new();
+    if ctx.record_enforced(&node_name) {
+        if let Some(record) = get_record(&ctx, &node_name).await? {
+            record_expiry = record.expiry;
+            for taint in record.taints {
+                if !taints_to_preserve.iter().any(|t| t.key == taint.key) {
+                    taints_to_preserve.push(taint);
+                }
+            }
+        }
+    }
+    let mut taint_expiry = expiry::taint_expiry(&node, &taints_to_preserve, &config);
+    for (key, time) in record_expiry {
+        taint_expiry.entry(key).or_insert(time);
+    }
 
     debug!(
-        "Taints to preserve for node '{}': {:?}",
-        node_name, taints_to_preserve
+        node = %node_name,
+        phase = "cleanup",
+        taints = ?taints_to_preserve,
+        "Taints to preserve"
     );
 
-    let cm_name = configmap_name(&node_name);
-    let mut cm_data = BTreeMap::new();
-
-    // Always write ConfigMap, even if empty, to avoid restoring stale taints
-    if !taints_to_preserve.is_empty() {
-        let taints_json =
-            serde_json::to_string(&taints_to_preserve).map_err(Error::Serialization)?;
-        cm_data.insert(JSON_STORAGE_KEY.to_string(), taints_json);
-    }
-
-    let mut cm_annotations = BTreeMap::new();
-    cm_annotations.insert(CONFIGMAP_NODE_ANNOTATION.to_string(), node_name.clone());
-
-    let cm = ConfigMap {
-        metadata: ObjectMeta {
-            name: Some(cm_name.clone()),
-            namespace: Some(ctx.configmap_namespace.clone()),
-            annotations: Some(cm_annotations),
-            ..Default::default()
-        },
-        data: Some(cm_data),
-        binary_data: None,
-        immutable: None,
-    };
-
-    let patch_params = PatchParams::apply(SERVICE_NAME).force();
-    ctx.cm_api()
-        .patch(&cm_name, &patch_params, &Patch::Apply(&cm))
-        .await
-        .map_err(|e| {
-            ERRORS_TOTAL
-                .with_label_values(&["configmap", "patch_error"])
-                .inc();
-            Error::Kube(e)
-        })?;
+    let stored = store_record(
+        &ctx,
+        &node_name,
+        &taints_to_preserve,
+        &taint_expiry,
+        CaptureReason::NodeDeleted,
+    )
+    .await?;
+    if stored < taints_to_preserve.len() {
+        report_truncated_record(&ctx, &node, stored, taints_to_preserve.len()).await;
+        taints_to_preserve.truncate(stored);
+    }
+    for taint in &taints_to_preserve {
+        TAINTS_CAPTURED_TOTAL
+            .with_label_values(&[node_label(&config, &node_name), &taint.key])
+            .inc();
+    }
+    ctx.forget_taints(&node_name);
+    breaker::record_cleanup(&ctx, &node_name).await;
 
     info!(
-        "Stored {} custom taints for node '{}'",
-        taints_to_preserve.len(),
-        node_name
+        node = %node_name,
+        phase = "cleanup",
+        record = %configmap_name(&node_name),
+        action = "store",
+        "Stored {} custom taints",
+        taints_to_preserve.len()
     );
 
     Ok(Action::await_change())
 }
 
-/// Emit a Kubernetes Event
-async fn emit_event(ctx: &Context, node_name: &str, reason: &str, message: &str, event_type: &str) {
-    let events_api: Api<Event> = Api::namespaced(ctx.client.clone(), &ctx.configmap_namespace);
-    let now = SystemTime::now();
-    let timestamp = now
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_secs();
-    let event_name = format!("{}.{}", node_name, timestamp);
-
-    let event = Event {
-        metadata: ObjectMeta {
-            name: Some(event_name),
-            namespace: Some(ctx.configmap_namespace.clone()),
-            ..Default::default()
-        },
-        involved_object: ObjectReference {
-            api_version: Some("v1".to_string()),
-            kind: Some("Node".to_string()),
-            name: Some(node_name.to_string()),
-            ..Default::default()
-        },
-        reason: Some(reason.to_string()),
-        message: Some(message.to_string()),
-        type_: Some(event_type.to_string()),
-        action: Some("Reconcile".to_string()),
-        reporting_component: Some(SERVICE_NAME.to_string()),
-        reporting_instance: Some(
-            std::env::var("HOSTNAME").unwrap_or_else(|_| "unknown".to_string()),
+/// Give up on a cleanup that kept failing for longer than the finalizer
+/// timeout: make one best-effort attempt to store the last-known taints,
+/// then let the finalizer be removed
+async fn cleanup_timed_out(node: &Node, ctx: &Context) -> Result<Action> {
+    let node_name = node.name_any();
+    let timeout_secs = ctx.config().finalizer_timeout_seconds;
+    warn!(
+        node = %node_name,
+        phase = "cleanup",
+        action = "remove_finalizer",
+        "Termination cleanup failed for over {}s. Forcing finalizer removal.",
+        timeout_secs
+    );
+    ERRORS_TOTAL
+        .with_label_values(&["cleanup", "timeout"])
+        .inc();
+
+    let taints = ctx.last_known_taints(&node_name).unwrap_or_default();
+    let taint_expiry = expiry::taint_expiry(node, &taints, &ctx.config());
+    let stored = store_record(
+        ctx,
+        &node_name,
+        &taints,
+        &taint_expiry,
+        CaptureReason::CleanupTimedOut,
+    )
+    .await;
+    let (outcome, message) = match stored {
+        Ok(stored) => {
+            if stored < taints.len() {
+                report_truncated_record(ctx, node, stored, taints.len()).await;
+            }
+            (
+                "fallback_stored",
+                format!(
+                    "Cleanup failed for over {}s, stored {} last-known taints before removing the finalizer",
+                    timeout_secs, stored
+                ),
+            )
+        }
+        Err(e) => (
+            "state_lost",
+            format!(
+                "Cleanup failed for over {}s and the fallback snapshot could not be stored ({}), taints of this node may be lost",
+                timeout_secs, e
+            ),
         ),
-        ..Default::default()
     };
+    warn!(
+        node = %node_name,
+        phase = "cleanup",
+        record = %configmap_name(&node_name),
+        action = outcome,
+        "{}",
+        message
+    );
+    FINALIZER_TIMEOUTS_TOTAL
+        .with_label_values(&[node_label(&ctx.config(), &node_name), outcome])
+        .inc();
+    emit_event(ctx, node, "CleanupTimedOut", &message, EventType::Warning).await;
+    ctx.forget_taints(&node_name);
+
+    Ok(Action::await_change())
+}
 
-    if let Err(e) = events_api.create(&PostParams::default(), &event).await {
-        warn!("Failed to create event: {:?}", e);
+/// Report a record stored with its last taints dropped to fit the size limit
+async fn report_truncated_record(ctx: &Context, node: &Node, stored: usize, total: usize) {
+    let node_name = node.name_any();
+    let message = format!(
+        "Record exceeded the size limit, stored only the first {} of {} taints",
+        stored, total
+    );
+    warn!(
+        node = %node_name,
+        phase = "cleanup",
+        record = %configmap_name(&node_name),
+        action = "truncate",
+        "{}",
+        message
+    );
+    RECORDS_TRUNCATED_TOTAL
+        .with_label_values(&[node_label(&ctx.config(), &node_name)])
+        .inc();
+    emit_event(ctx, node, "RecordTruncated", &message, EventType::Warning).await;
+}
+
+/// Add the standard labels to records written without them, so they are
+/// found by the record selector. Returns the nodes whose records were adopted.
+pub async fn adopt_records(ctx: &Context) -> Result<Vec<String>> {
+    let cm_api = ctx.cm_api();
+    // Only ConfigMaps without the node hash label can be unlabeled records
+    let list_params = ListParams::default().labels(&format!("!{}", NODE_HASH_LABEL));
+    let mut adopted = Vec::new();
+    for cm in observe_api_call("configmaps", "list", cm_api.list(&list_params)).await? {
+        let Some(node_name) = cm.annotations().get(CONFIGMAP_NODE_ANNOTATION) else {
+            continue;
+        };
+        let labels = record_labels(node_name);
+        if labels
+            .iter()
+            .all(|(key, value)| cm.labels().get(key) == Some(value))
+        {
+            continue;
+        }
+
+        let patch = serde_json::json!({ "metadata": { "labels": labels } });
+        observe_api_call(
+            "configmaps",
+            "patch",
+            cm_api.patch(
+                &cm.name_any(),
+                &PatchParams::default(),
+                &Patch::Merge(&patch),
+            ),
+        )
+        .await?;
+        info!(node = %node_name, record = %cm.name_any(), action = "adopt", "Adopted record");
+        adopted.push(node_name.clone());
     }
+    Ok(adopted)
+}
+
+/// Emit a Kubernetes Event about a Node, referencing it by UID
+async fn emit_event(
+    ctx: &Context,
+    node: &Node,
+    reason: &str,
+    message: &str,
+    event_type: EventType,
+) {
+    let span = info_span!(
+        "emit_event",
+        node = %node.name_any(),
+        uid = node.uid().as_deref(),
+        reason,
+        outcome = field::Empty
+    );
+    emit_event_for(ctx, node.object_ref(&()), reason, message, event_type)
+        .instrument(span)
+        .await;
+}
+
+/// Emit a Kubernetes Event about any object
+async fn emit_event_for(
+    ctx: &Context,
+    regarding: ObjectReference,
+    reason: &str,
+    message: &str,
+    event_type: EventType,
+) {
+    // Lets the Event be matched with the trace of the reconcile emitting it
+    let message = match telemetry::trace_id(&Span::current()) {
+        Some(trace_id) => format!("{} (trace_id={})", message, trace_id),
+        None => message.to_string(),
+    };
+    let outcome = ctx
+        .recorder
+        .publish(&regarding, event_type, reason, &message)
+        .await;
+    Span::current().record("outcome", outcome);
 }
 
 /// Exponential backoff on error
 pub fn error_policy(_node: Arc<Node>, error: &Error, ctx: Arc<Context>) -> Action {
-    error!("Reconciliation failed: {:?}", error);
+    error!(error = ?error, "Reconciliation failed");
     let attempt = ctx.attempt.fetch_add(1, Ordering::SeqCst) + 1;
-    let base_secs = REQUEUE_TIME.as_secs();
-    let max_secs = MAX_RETRY_TIME.as_secs();
+    let config = ctx.config();
+    let base_secs = config.requeue_seconds;
+    let max_secs = config.max_retry_seconds;
     let factor = 2u64.checked_pow(attempt).unwrap_or(u64::MAX);
     let delay_s = base_secs.saturating_mul(factor).min(max_secs);
     Action::requeue(Duration::from_secs(delay_s))
 }
+
+/// Drive the controller until it stops after a shutdown request, or until
+/// the grace period after the request expires. Returns the reconciles that
+/// were still in flight and are interrupted, each reported with a warning.
+pub async fn drain_on_shutdown(
+    ctx: &Context,
+    controller: impl Future<Output = ()>,
+    shutdown: impl Future<Output = ()>,
+    grace: Duration,
+) -> Vec<(String, &'static str)> {
+    // Read before the select drops the controller, and with it the guards
+    // of its in-flight reconciles
+    let grace_expired = async {
+        shutdown.await;
+        tokio::time::sleep(grace).await;
+        ctx.in_flight()
+    };
+
+    tokio::select! {
+        _ = controller => {
+            info!("Controller stopped, no reconciles were interrupted");
+            Vec::new()
+        }
+        interrupted = grace_expired => {
+            for (node_name, phase) in &interrupted {
+                warn!(
+                    node = %node_name,
+                    phase,
+                    "Shutdown grace period of {}s expired, interrupting reconcile",
+                    grace.as_secs()
+                );
+            }
+            interrupted
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use axum::{extract::State, http::StatusCode, routing::get, Router};
+use std::sync::{Arc, Mutex};
+use tracing::info;
+use tracing_subscriber::{
+    filter::ParseError,
+    fmt::{
+        self,
+        format::{Format, Json, JsonFields},
+        MakeWriter,
+    },
+    registry::LookupSpan,
+    reload, EnvFilter, Registry,
+};
+
+/// Changes the log filter of the running process
+pub struct LogControl {
+    handle: reload::Handle<EnvFilter, Registry>,
+    directives: Mutex<String>,
+}
+
+impl LogControl {
+    /// Reloadable filter layer starting with the given directives, and the
+    /// control changing it
+    pub fn new(directives: &str) -> Result<(Self, reload::Layer<EnvFilter, Registry>), ParseError> {
+        let (layer, handle) = reload::Layer::new(EnvFilter::try_new(directives)?);
+        let control = Self {
+            handle,
+            directives: Mutex::new(directives.to_string()),
+        };
+        Ok((control, layer))
+    }
+
+    /// Current filter directives
+    pub fn directives(&self) -> String {
+        self.directives.lock().unwrap().clone()
+    }
+
+    /// Replace the filter directives, leaving them unchanged if invalid
+    pub fn set_directives(&self, directives: &str) -> Result<(), String> {
+        let filter = EnvFilter::try_new(directives).map_err(|e| e.to_string())?;
+        self.handle.reload(filter).map_err(|e| e.to_string())?;
+        *self.directives.lock().unwrap() = directives.to_string();
+        info!(log_filter = directives, "Changed log filter");
+        Ok(())
+    }
+}
+
+/// Layer writing one JSON object per line, with the event fields at the top
+/// level. The current span and every span it is nested in are included, so
+/// lines logged in child spans carry the `trace_id` of their `reconcile` span.
+pub fn json_layer<S, W>(make_writer: W) -> fmt::Layer<S, JsonFields, Format<Json>, W>
+where
+    S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+    W: for<'writer> MakeWriter<'writer> + 'static,
+{
+    fmt::layer()
+        .json()
+        .flatten_event(true)
+        .with_current_span(true)
+        .with_span_list(true)
+        .with_writer(make_writer)
+}
+
+/// `GET /log-level` returns the filter directives, `PUT /log-level` with
+/// new directives as the body replaces them
+pub fn log_level_routes(control: Arc<LogControl>) -> Router {
+    Router::new()
+        .route("/log-level", get(get_log_level).put(put_log_level))
+        .with_state(control)
+}
+
+async fn get_log_level(State(control): State<Arc<LogControl>>) -> String {
+    control.directives()
+}
+
+async fn put_log_level(
+    State(control): State<Arc<LogControl>>,
+    body: String,
+) -> (StatusCode, String) {
+    match control.set_directives(body.trim()) {
+        Ok(()) => (StatusCode::OK, control.directives()),
+        Err(e) => (StatusCode::BAD_REQUEST, e),
+    }
+}
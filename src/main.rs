@@ -1,43 +1,344 @@
+use clap::{Parser, Subcommand};
 use futures::stream::StreamExt;
-use k8s_openapi::api::core::v1::Node;
-use kube::{
-    api::Api,
-    runtime::{controller::Controller, watcher},
-    Client,
+use k8s_openapi::api::core::v1::{Node, Taint};
+use kube::{api::Api, runtime::controller::Controller, Client};
+use node_taint_preserver::{
+    adopt_records,
+    breaker::{resume, sync_circuit_breaker},
+    config::watch_config_file,
+    crypto::Keyring,
+    drain_on_shutdown,
+    drift::{detect_drift, run_drift_scans},
+    error_policy,
+    history::{node_history, restore_snapshot},
+    logging::{self, LogControl},
+    reconcile, serve_admin, serve_metrics,
+    storage::{migrate_records, reseal_records},
+    telemetry,
+    uninstall::uninstall,
+    Config, ConfigOverrides, Context, LogFormat,
 };
-use node_taint_preserver::{error_policy, reconcile, Context};
+use opentelemetry_sdk::trace::SdkTracerProvider;
 use std::sync::Arc;
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::watch,
+};
 use tracing::{info, warn};
 use tracing_subscriber::prelude::*;
 
+#[derive(Parser)]
+#[command(version, about = "Preserves custom Node taints across node deletion")]
+struct Cli {
+    #[command(flatten)]
+    config: ConfigOverrides,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the controller (default)
+    Run,
+    /// Report drift between live node taints and their stored records
+    Drift {
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// List the snapshots of a node's taints kept in its record
+    History {
+        node: String,
+        /// Print the snapshots as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Replace the custom taints of a live node with a snapshot from its history
+    RestoreSnapshot {
+        node: String,
+        /// Index of the snapshot, as listed by `history`, 0 being the newest
+        #[arg(long, default_value_t = 0)]
+        snapshot: usize,
+        /// Only report the resulting taints, using server-side dry run
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Resume taint restores after the mass-deletion circuit breaker tripped
+    Resume,
+    /// Remove our finalizer and restore annotation from every node.
+    /// Stop the controller first, or it adds the finalizer back.
+    #[command(alias = "remove-finalizers")]
+    Uninstall {
+        /// Also delete all stored records
+        #[arg(long)]
+        delete_records: bool,
+        /// Only report what would change, using server-side dry run
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let filter = tracing_subscriber::filter::Targets::new()
-        .with_target("node_taint_preserver", tracing::Level::DEBUG);
+    let cli = Cli::parse();
+
+    let config = Config::load(&cli.config)?;
+
+    let tracer_provider = telemetry::tracer_provider(config.otlp_endpoint.as_deref())?;
+    let log_control = init_logging(&config, &tracer_provider)?;
+
+    let client = Client::try_default().await?;
+    let keyring = Keyring::load(&config.record_encryption_key_files)?;
+    let context = Arc::new(Context::new(client.clone(), config).with_keyring(keyring));
+
+    let result = execute(cli, client, context, log_control).await;
+
+    // Export the spans still buffered before exiting
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = tracer_provider.shutdown() {
+            warn!(error = ?e, "Failed to flush traces");
+        }
+    })
+    .await?;
+    result
+}
+
+/// Install the global subscriber with the configured filter and format,
+/// exporting spans to the tracer provider
+fn init_logging(
+    config: &Config,
+    tracer_provider: &SdkTracerProvider,
+) -> anyhow::Result<Arc<LogControl>> {
+    let (log_control, filter) = LogControl::new(&config.log_filter)?;
+    let (text, json) = match config.log_format {
+        LogFormat::Text => (Some(tracing_subscriber::fmt::layer()), None),
+        LogFormat::Json => (None, Some(logging::json_layer(std::io::stdout))),
+    };
     tracing_subscriber::registry()
-        .with(tracing_subscriber::fmt::layer())
         .with(filter)
-        .init();
+        .with(text)
+        .with(json)
+        .with(telemetry::layer(tracer_provider))
+        .try_init()?;
+    Ok(Arc::new(log_control))
+}
 
-    let client = Client::try_default().await?;
+/// Run the requested subcommand
+async fn execute(
+    cli: Cli,
+    client: Client,
+    context: Arc<Context>,
+    log_control: Arc<LogControl>,
+) -> anyhow::Result<()> {
+    match cli.command {
+        None | Some(Command::Run) => run(client, context, cli.config, log_control).await,
+        Some(Command::Drift { json }) => drift(context, json).await,
+        Some(Command::History { node, json }) => history(context, &node, json).await,
+        Some(Command::RestoreSnapshot {
+            node,
+            snapshot,
+            dry_run,
+        }) => {
+            let taints = restore_snapshot(&context, &node, snapshot, dry_run).await?;
+            let prefix = if dry_run { "[dry run] " } else { "" };
+            println!("{}Restored snapshot {} onto {}:", prefix, snapshot, node);
+            for taint in &taints {
+                println!("  {}", format_taint(taint));
+            }
+            Ok(())
+        }
+        Some(Command::Resume) => {
+            if resume(&context).await? {
+                println!("Circuit breaker resumed, taint restores continue");
+            } else {
+                println!("Circuit breaker is not open");
+            }
+            Ok(())
+        }
+        Some(Command::Uninstall {
+            delete_records,
+            dry_run,
+        }) => {
+            let report = uninstall(&context, delete_records, dry_run).await?;
+            let prefix = if dry_run { "[dry run] " } else { "" };
+            println!(
+                "{}Released {} nodes: {}",
+                prefix,
+                report.nodes_released.len(),
+                report.nodes_released.join(", ")
+            );
+            if delete_records {
+                println!(
+                    "{}Deleted records of {} nodes: {}",
+                    prefix,
+                    report.records_deleted.len(),
+                    report.records_deleted.join(", ")
+                );
+            }
+            if !report.nodes_failed.is_empty() {
+                for (node, error) in &report.nodes_failed {
+                    eprintln!("{}Failed to release node {}: {}", prefix, node, error);
+                }
+                anyhow::bail!("Failed to release {} nodes", report.nodes_failed.len());
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Run the controller until shutdown is requested
+async fn run(
+    client: Client,
+    context: Arc<Context>,
+    overrides: ConfigOverrides,
+    log_control: Arc<LogControl>,
+) -> anyhow::Result<()> {
     let node_api: Api<Node> = Api::all(client.clone());
-    let context = Arc::new(Context::new(client.clone()));
+    let config = context.config();
+    let metrics_addr = config.metrics_addr;
+    let admin_addr = config.admin_addr;
+    let grace = config.shutdown_grace();
 
-    let configmap_namespace =
-        std::env::var("CONFIGMAP_NAMESPACE").unwrap_or_else(|_| "default".to_string());
     info!(
         "Starting Node Taint Preserver controller, storing in namespace {}...",
-        configmap_namespace
+        config.configmap_namespace
     );
+    info!("Watching nodes with {}", context.describe_scope());
+
+    // Before the record watch starts, so its cache lists adopted records
+    match adopt_records(&context).await {
+        Ok(adopted) if !adopted.is_empty() => {
+            info!("Adopted {} records written without labels", adopted.len())
+        }
+        Ok(_) => {}
+        Err(e) => warn!(error = ?e, "Failed to adopt unlabeled records"),
+    }
+    let migration_context = context.clone();
+    tokio::spawn(async move {
+        match migrate_records(&migration_context).await {
+            Ok(migrated) if !migrated.is_empty() => {
+                info!("Moved {} records into shards", migrated.len())
+            }
+            Ok(_) => {}
+            Err(e) => warn!(error = ?e, "Failed to move records into shards"),
+        }
+        match reseal_records(&migration_context).await {
+            Ok(resealed) if !resealed.is_empty() => {
+                info!(
+                    "Re-encrypted {} records with the current key",
+                    resealed.len()
+                )
+            }
+            Ok(_) => {}
+            Err(e) => warn!(error = ?e, "Failed to re-encrypt records"),
+        }
+    });
+
+    tokio::spawn(async move {
+        if let Err(e) = serve_metrics(metrics_addr).await {
+            warn!(error = ?e, "Metrics server failed");
+        }
+    });
+    tokio::spawn(async move {
+        if let Err(e) = serve_admin(admin_addr, log_control).await {
+            warn!(error = ?e, "Admin server failed");
+        }
+    });
+    tokio::spawn(run_drift_scans(context.clone()));
+    tokio::spawn(watch_config_file(context.clone(), overrides));
+    tokio::spawn(sync_circuit_breaker(context.clone()));
+
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let mut sigterm = signal(SignalKind::terminate())?;
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = sigterm.recv() => info!("Received SIGTERM, shutting down"),
+            _ = tokio::signal::ctrl_c() => info!("Received SIGINT, shutting down"),
+        }
+        shutdown_tx.send(true).ok();
+    });
 
-    Controller::new(node_api, watcher::Config::default())
-        .run(reconcile, error_policy, context)
+    // The controller stops accepting new work on shutdown, and its stream
+    // ends once the in-flight reconciles have finished
+    let records_context = context.clone();
+    let controller = Controller::new(node_api, context.node_watcher_config())
+        .watches_stream(context.watch_records(), move |cm| {
+            records_context.observe_record(&cm)
+        })
+        .graceful_shutdown_on(shutdown_requested(shutdown_rx.clone()))
+        .run(reconcile, error_policy, context.clone())
         .for_each(|res| async move {
             match res {
-                Ok((obj, _action)) => info!("Reconciled Node '{}'", obj.name),
-                Err(e) => warn!("Reconciliation error: {:?}", e),
+                Ok((obj, action)) => info!(node = %obj.name, action = ?action, "Reconciled node"),
+                Err(e) => warn!(error = ?e, "Reconciliation error"),
             }
-        })
-        .await;
+        });
+    drain_on_shutdown(&context, controller, shutdown_requested(shutdown_rx), grace).await;
     Ok(())
 }
+
+/// Resolves once shutdown has been requested
+async fn shutdown_requested(mut shutdown_rx: watch::Receiver<bool>) {
+    shutdown_rx.wait_for(|requested| *requested).await.ok();
+}
+
+/// Print drift between live nodes and their stored records
+async fn drift(context: Arc<Context>, json: bool) -> anyhow::Result<()> {
+    let report = detect_drift(&context).await?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+
+    let drifted: Vec<_> = report.iter().filter(|d| !d.is_empty()).collect();
+    for drift in &drifted {
+        println!("{}:", drift.node);
+        for taint in &drift.added {
+            println!("  + {}", format_taint(taint));
+        }
+        for taint in &drift.removed {
+            println!("  - {}", format_taint(taint));
+        }
+        for (stored, live) in &drift.changed {
+            println!("  ~ {} -> {}", format_taint(stored), format_taint(live));
+        }
+    }
+    println!(
+        "{} of {} nodes with records have drifted",
+        drifted.len(),
+        report.len()
+    );
+    Ok(())
+}
+
+/// Print the snapshot history of a node
+async fn history(context: Arc<Context>, node: &str, json: bool) -> anyhow::Result<()> {
+    let history = node_history(&context, node).await?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&history)?);
+        return Ok(());
+    }
+
+    for (index, snapshot) in history.iter().enumerate() {
+        println!(
+            "{} {} {}:",
+            index,
+            snapshot.captured_at.0.to_rfc3339(),
+            snapshot.reason.as_str()
+        );
+        for taint in &snapshot.taints {
+            println!("  {}", format_taint(taint));
+        }
+    }
+    println!("{} snapshots of {}", history.len(), node);
+    Ok(())
+}
+
+/// Format a taint the way kubectl does, as key=value:effect
+fn format_taint(taint: &Taint) -> String {
+    match &taint.value {
+        Some(value) => format!("{}={}:{}", taint.key, value, taint.effect),
+        None => format!("{}:{}", taint.key, taint.effect),
+    }
+}
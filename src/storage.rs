@@ -0,0 +1,1286 @@
+use crate::{
+    config::RecordCompression,
+    configmap_name,
+    crypto::{Envelope, Keyring},
+    node_hash, node_label, observe_api_call, record_labels, record_list_params, Context, Error,
+    Result, StorageLayout, COMPONENT_LABEL, CONFIGMAP_NODE_ANNOTATION, ENFORCED_RECORD_ANNOTATION,
+    ERRORS_TOTAL, JSON_STORAGE_KEY, MANAGED_BY_LABEL, NO_RECORD_REVISION, RECORDS_TRUNCATED_TOTAL,
+    RECORD_CACHE_LOOKUPS_TOTAL, RECORD_COMPONENT, SERVICE_NAME,
+};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use k8s_openapi::{
+    api::core::v1::{ConfigMap, Taint},
+    apimachinery::pkg::apis::meta::v1::{ObjectMeta, Time},
+    chrono::Utc,
+    ByteString,
+};
+use kube::{
+    api::{DeleteParams, Patch, PatchParams, PostParams, Preconditions, ResourceExt},
+    error::ErrorResponse,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    io::{Read, Write},
+};
+use tracing::{debug, info, warn};
+
+/// Label holding the hash prefix of the nodes stored in a shard
+pub(crate) const SHARD_LABEL: &str = "nodetaintpreserver.example.com/shard";
+const SHARD_NAME_PREFIX: &str = "node-taints-shard-";
+// Leaves room below the 1MiB object limit for metadata, including the
+// managedFields entry every data key adds
+const MAX_SHARD_DATA_BYTES: usize = 768 * 1024;
+/// Longest valid shard prefix, for 65536 shards
+pub(crate) const MAX_SHARD_PREFIX_LENGTH: usize = 4;
+// Attempts at a shard update that keeps losing resourceVersion races
+const MAX_SHARD_WRITE_ATTEMPTS: usize = 5;
+/// Data key of the snapshot history of per-node records
+const HISTORY_STORAGE_KEY: &str = "taints_history_json";
+/// Data key of the taint expiry times of per-node records
+const EXPIRY_STORAGE_KEY: &str = "taints_expiry_json";
+/// Suffix of the binary data key of a gzipped per-node record document
+const GZIP_SUFFIX: &str = ".gz";
+/// Suffix of the data key of an encrypted per-node record document
+const SEALED_SUFFIX: &str = ".sealed";
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+// Per-node records with larger JSON are compressed
+const COMPRESSION_THRESHOLD_BYTES: usize = 64 * 1024;
+// Leaves room below the 1MiB object limit for metadata
+const MAX_RECORD_BYTES: usize = 900 * 1024;
+// Lets a shard hold at least a dozen records of the maximum size
+const MAX_SHARD_ENTRY_BYTES: usize = 64 * 1024;
+
+/// Why a snapshot of a node's taints was captured
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CaptureReason {
+    /// The node was deleted
+    NodeDeleted,
+    /// Cleanup kept failing and the last-known taints were stored
+    CleanupTimedOut,
+}
+
+impl CaptureReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CaptureReason::NodeDeleted => "node-deleted",
+            CaptureReason::CleanupTimedOut => "cleanup-timed-out",
+        }
+    }
+}
+
+/// Taints of a node at the time it was deleted
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub captured_at: Time,
+    pub reason: CaptureReason,
+    pub taints: Vec<Taint>,
+    /// Expiry times of the taints, empty in snapshots captured before
+    /// expiry times were kept
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub expiry: BTreeMap<String, Time>,
+}
+
+/// Stored taints of a node, kept in its own ConfigMap or in an entry of a
+/// shard ConfigMap
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    pub node: String,
+    pub taints: Vec<Taint>,
+    /// Taints are desired state, re-applied whenever they go missing
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub enforced: bool,
+    /// Snapshots of the taints, newest first, the first one being the
+    /// current taints. Empty for records written without history.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<Snapshot>,
+    /// When the stored taints expire, by key, for the ones that do
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub expiry: BTreeMap<String, Time>,
+    /// Identifies the stored taints, to notice records that changed after
+    /// they were restored onto a node
+    #[serde(skip)]
+    pub revision: String,
+}
+
+/// Revision of the stored taints JSON
+fn taints_revision(taints_json: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(taints_json.as_bytes());
+    hex::encode(hasher.finalize())[..16].to_string()
+}
+
+/// Revision of a record, or of a missing one
+pub(crate) fn record_revision(record: Option<&Record>) -> String {
+    match record {
+        Some(record) => record.revision.clone(),
+        None => NO_RECORD_REVISION.to_string(),
+    }
+}
+
+/// Stored form of a JSON document of a per-node record
+enum Payload {
+    Json(String),
+    Gzip(Vec<u8>),
+    /// Envelope JSON of the plain or gzipped JSON
+    Sealed(String),
+}
+
+impl Payload {
+    /// Bytes taken in the ConfigMap, with binary data base64-encoded
+    fn size(&self) -> usize {
+        match self {
+            Payload::Json(json) | Payload::Sealed(json) => json.len(),
+            Payload::Gzip(bytes) => bytes.len().div_ceil(3) * 4,
+        }
+    }
+
+    /// Add to the data of a ConfigMap, under `key` with a suffix telling how
+    /// it was encoded
+    fn insert_into(
+        self,
+        key: &str,
+        data: &mut BTreeMap<String, String>,
+        binary_data: &mut BTreeMap<String, ByteString>,
+    ) {
+        match self {
+            Payload::Json(json) => {
+                data.insert(key.to_string(), json);
+            }
+            Payload::Gzip(bytes) => {
+                binary_data.insert(format!("{}{}", key, GZIP_SUFFIX), ByteString(bytes));
+            }
+            Payload::Sealed(envelope_json) => {
+                data.insert(format!("{}{}", key, SEALED_SUFFIX), envelope_json);
+            }
+        }
+    }
+}
+
+/// Encode a JSON document of a node's record, gzipped when compression is
+/// enabled and it is large, then encrypted when the keyring has keys
+fn encode_json(
+    keyring: &Keyring,
+    node_name: &str,
+    json: String,
+    compression: RecordCompression,
+) -> Result<Payload> {
+    let plaintext =
+        if compression == RecordCompression::None || json.len() <= COMPRESSION_THRESHOLD_BYTES {
+            if !keyring.is_enabled() {
+                return Ok(Payload::Json(json));
+            }
+            json.into_bytes()
+        } else {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder
+                .write_all(json.as_bytes())
+                .map_err(Error::Compression)?;
+            let bytes = encoder.finish().map_err(Error::Compression)?;
+            if !keyring.is_enabled() {
+                return Ok(Payload::Gzip(bytes));
+            }
+            bytes
+        };
+    let envelope = keyring.seal(node_name, &plaintext)?;
+    Ok(Payload::Sealed(serde_json::to_string(&envelope)?))
+}
+
+/// Decrypt a JSON document of a node's record, gzipped or not, from its
+/// envelope
+fn open_json(keyring: &Keyring, node_name: &str, envelope: &Envelope) -> Result<String> {
+    let plaintext = keyring.open(node_name, envelope)?;
+    if plaintext.starts_with(&GZIP_MAGIC) {
+        return decompress(&plaintext);
+    }
+    String::from_utf8(plaintext).map_err(|e| Error::Encryption(e.to_string()))
+}
+
+/// Decompress gzipped JSON
+fn decompress(bytes: &[u8]) -> Result<String> {
+    let mut json = String::new();
+    GzDecoder::new(bytes)
+        .read_to_string(&mut json)
+        .map_err(Error::Compression)?;
+    Ok(json)
+}
+
+/// Read a JSON document added to a per-node record by `Payload::insert_into`
+fn read_json(
+    keyring: &Keyring,
+    node_name: &str,
+    cm: &ConfigMap,
+    key: &str,
+) -> Result<Option<String>> {
+    let data = cm.data.as_ref();
+    if let Some(sealed) = data.and_then(|data| data.get(&format!("{}{}", key, SEALED_SUFFIX))) {
+        let envelope = serde_json::from_str(sealed).map_err(Error::Serialization)?;
+        return open_json(keyring, node_name, &envelope).map(Some);
+    }
+    if let Some(json) = data.and_then(|data| data.get(key)) {
+        return Ok(Some(json.clone()));
+    }
+    cm.binary_data
+        .as_ref()
+        .and_then(|data| data.get(&format!("{}{}", key, GZIP_SUFFIX)))
+        .map(|bytes| decompress(&bytes.0))
+        .transpose()
+}
+
+/// Encode the longest prefix of `items` whose encoding, sized by `encode`,
+/// fits in `limit` bytes. Returns the encoding and the number of items kept.
+fn fit_prefix<I, T>(
+    items: &[I],
+    limit: usize,
+    encode: impl Fn(&[I]) -> Result<(T, usize)>,
+) -> Result<(T, usize)> {
+    let (encoded, size) = encode(items)?;
+    if size <= limit {
+        return Ok((encoded, items.len()));
+    }
+    // Binary search between a prefix that fits, starting with the empty
+    // one, and one that does not
+    let (mut fits, mut too_long) = (0, items.len());
+    while too_long - fits > 1 {
+        let mid = (fits + too_long) / 2;
+        if encode(&items[..mid])?.1 <= limit {
+            fits = mid;
+        } else {
+            too_long = mid;
+        }
+    }
+    let (encoded, _) = encode(&items[..fits])?;
+    Ok((encoded, fits))
+}
+
+/// Add a snapshot of the taints being stored in front of the history,
+/// keeping at most `length` snapshots
+fn push_snapshot(
+    mut history: Vec<Snapshot>,
+    taints: &[Taint],
+    expiry: &BTreeMap<String, Time>,
+    reason: CaptureReason,
+    length: usize,
+) -> Vec<Snapshot> {
+    history.insert(
+        0,
+        Snapshot {
+            captured_at: Time(Utc::now()),
+            reason,
+            taints: taints.to_vec(),
+            expiry: expiry_of(taints, expiry),
+        },
+    );
+    history.truncate(length);
+    history
+}
+
+/// Name of the shard ConfigMap holding the record of a node
+pub(crate) fn shard_name(node_name: &str, prefix_length: usize) -> String {
+    format!(
+        "{}{}",
+        SHARD_NAME_PREFIX,
+        &node_hash(node_name)[..prefix_length]
+    )
+}
+
+/// Record kept in a per-node ConfigMap
+fn node_record(keyring: &Keyring, cm: &ConfigMap) -> Result<Option<Record>> {
+    let Some(node_name) = cm.annotations().get(CONFIGMAP_NODE_ANNOTATION) else {
+        return Ok(None);
+    };
+    let taints_json = read_json(keyring, node_name, cm, JSON_STORAGE_KEY)?;
+    let taints = match &taints_json {
+        Some(taints_json) => serde_json::from_str(taints_json).map_err(Error::Serialization)?,
+        None => Vec::new(),
+    };
+    let history = match read_json(keyring, node_name, cm, HISTORY_STORAGE_KEY)? {
+        Some(history_json) => serde_json::from_str(&history_json)?,
+        None => Vec::new(),
+    };
+    let expiry = match read_json(keyring, node_name, cm, EXPIRY_STORAGE_KEY)? {
+        Some(expiry_json) => serde_json::from_str(&expiry_json)?,
+        None => BTreeMap::new(),
+    };
+    Ok(Some(Record {
+        node: node_name.clone(),
+        taints,
+        enforced: cm
+            .annotations()
+            .get(ENFORCED_RECORD_ANNOTATION)
+            .is_some_and(|v| v == "true"),
+        history,
+        expiry,
+        revision: taints_revision(taints_json.as_deref().unwrap_or_default()),
+    }))
+}
+
+/// Whether every document of a per-node record, its taints, expiry times
+/// and history, is encrypted with the given key
+fn node_record_sealed_with(cm: &ConfigMap, key_id: &str) -> bool {
+    let data = cm.data.clone().unwrap_or_default();
+    let binary_data = cm.binary_data.clone().unwrap_or_default();
+    [JSON_STORAGE_KEY, EXPIRY_STORAGE_KEY, HISTORY_STORAGE_KEY]
+        .iter()
+        .all(|key| {
+            let unsealed = data.contains_key(*key)
+                || binary_data.contains_key(&format!("{}{}", key, GZIP_SUFFIX));
+            let sealed_with_other_key =
+                data.get(&format!("{}{}", key, SEALED_SUFFIX))
+                    .is_some_and(|sealed| {
+                        serde_json::from_str::<Envelope>(sealed)
+                            .map_or(true, |envelope| envelope.key_id != key_id)
+                    });
+            !unsealed && !sealed_with_other_key
+        })
+}
+
+/// Stored form of a shard entry, with the taints sealed instead when
+/// records are encrypted
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    node: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    taints: Option<Vec<Taint>>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    enforced: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sealed: Option<Envelope>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    history: Option<Vec<Snapshot>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sealed_history: Option<Envelope>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expiry: Option<BTreeMap<String, Time>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sealed_expiry: Option<Envelope>,
+}
+
+/// Expiry times of the given taints
+fn expiry_of(taints: &[Taint], expiry: &BTreeMap<String, Time>) -> BTreeMap<String, Time> {
+    expiry
+        .iter()
+        .filter(|(key, _)| taints.iter().any(|t| &t.key == *key))
+        .map(|(key, time)| (key.clone(), time.clone()))
+        .collect()
+}
+
+/// Serialize a shard entry, encrypting the taints, their expiry times and
+/// the history when the keyring has keys
+fn encode_entry(
+    keyring: &Keyring,
+    node_name: &str,
+    taints: &[Taint],
+    expiry: &BTreeMap<String, Time>,
+    history: &[Snapshot],
+    enforced: bool,
+) -> Result<String> {
+    let expiry = expiry_of(taints, expiry);
+    let mut entry = Entry {
+        node: node_name.to_string(),
+        taints: Some(taints.to_vec()),
+        enforced,
+        sealed: None,
+        history: (!history.is_empty()).then(|| history.to_vec()),
+        sealed_history: None,
+        expiry: (!expiry.is_empty()).then_some(expiry),
+        sealed_expiry: None,
+    };
+    if keyring.is_enabled() {
+        let taints_json = serde_json::to_string(taints)?;
+        entry.sealed = Some(keyring.seal(node_name, taints_json.as_bytes())?);
+        entry.taints = None;
+        if let Some(history) = entry.history.take() {
+            let history_json = serde_json::to_string(&history)?;
+            entry.sealed_history = Some(keyring.seal(node_name, history_json.as_bytes())?);
+        }
+        if let Some(expiry) = entry.expiry.take() {
+            let expiry_json = serde_json::to_string(&expiry)?;
+            entry.sealed_expiry = Some(keyring.seal(node_name, expiry_json.as_bytes())?);
+        }
+    }
+    Ok(serde_json::to_string(&entry)?)
+}
+
+/// Parse a shard entry
+fn parse_entry(keyring: &Keyring, entry_json: &str) -> Result<Record> {
+    let entry: Entry = serde_json::from_str(entry_json).map_err(Error::Serialization)?;
+    let taints = match &entry.sealed {
+        Some(envelope) => serde_json::from_str(&open_json(keyring, &entry.node, envelope)?)?,
+        None => entry.taints.unwrap_or_default(),
+    };
+    let history = match &entry.sealed_history {
+        Some(envelope) => serde_json::from_str(&open_json(keyring, &entry.node, envelope)?)?,
+        None => entry.history.unwrap_or_default(),
+    };
+    let expiry = match &entry.sealed_expiry {
+        Some(envelope) => serde_json::from_str(&open_json(keyring, &entry.node, envelope)?)?,
+        None => entry.expiry.unwrap_or_default(),
+    };
+    Ok(Record {
+        revision: taints_revision(&serde_json::to_string(&taints)?),
+        node: entry.node,
+        taints,
+        enforced: entry.enforced,
+        history,
+        expiry,
+    })
+}
+
+/// Key the taints of a shard entry are encrypted with, if any
+fn entry_key_id(entry_json: &str) -> Option<String> {
+    let entry: Entry = serde_json::from_str(entry_json).ok()?;
+    Some(entry.sealed?.key_id)
+}
+
+/// Whether a shard entry is marked as enforced, read without decrypting it
+fn entry_enforced(entry_json: &str) -> bool {
+    serde_json::from_str::<Entry>(entry_json).is_ok_and(|entry| entry.enforced)
+}
+
+/// Record of a node kept in a shard ConfigMap
+fn shard_entry(keyring: &Keyring, cm: &ConfigMap, node_name: &str) -> Result<Option<Record>> {
+    cm.data
+        .as_ref()
+        .and_then(|data| data.get(&node_hash(node_name)))
+        .map(|entry_json| parse_entry(keyring, entry_json))
+        .transpose()
+}
+
+/// Whether a ConfigMap holds records, of one node or of a shard
+pub(crate) fn is_record_configmap(cm: &ConfigMap) -> bool {
+    cm.annotations().contains_key(CONFIGMAP_NODE_ANNOTATION)
+        || cm.labels().contains_key(SHARD_LABEL)
+}
+
+/// Every record held by a ConfigMap, skipping ones that cannot be parsed
+/// or decrypted
+pub(crate) fn records_in(keyring: &Keyring, cm: &ConfigMap) -> Vec<Record> {
+    if !cm.labels().contains_key(SHARD_LABEL) {
+        return match node_record(keyring, cm) {
+            Ok(record) => record.into_iter().collect(),
+            Err(e) => {
+                warn!(record = %cm.name_any(), error = %e, "Ignoring invalid record");
+                Vec::new()
+            }
+        };
+    }
+    cm.data
+        .iter()
+        .flatten()
+        .filter_map(|(key, entry_json)| match parse_entry(keyring, entry_json) {
+            Ok(record) => Some(record),
+            Err(e) => {
+                warn!(record = %cm.name_any(), entry = %key, error = %e, "Ignoring invalid shard entry");
+                None
+            }
+        })
+        .collect()
+}
+
+/// Records of every node, with the shard entry winning over a per-node
+/// record that was not migrated yet
+pub(crate) fn records_by_node(keyring: &Keyring, cms: &[ConfigMap]) -> BTreeMap<String, Record> {
+    let (shards, per_node): (Vec<&ConfigMap>, Vec<&ConfigMap>) = cms
+        .iter()
+        .partition(|cm| cm.labels().contains_key(SHARD_LABEL));
+    per_node
+        .into_iter()
+        .chain(shards)
+        .flat_map(|cm| records_in(keyring, cm))
+        .map(|record| (record.node.clone(), record))
+        .collect()
+}
+
+/// Read a record from a ConfigMap in the record cache, or from a live GET
+/// of the ConfigMap when the cache cannot tell: before it synced, or when
+/// it has not seen a write of this process yet. Once synced, a ConfigMap
+/// missing from the cache does not exist.
+async fn fetch_record(
+    ctx: &Context,
+    cm_name: &str,
+    read: impl Fn(&ConfigMap) -> Result<Option<Record>>,
+) -> Result<Option<Record>> {
+    if let Some(cm) = ctx.cached_configmap(cm_name) {
+        RECORD_CACHE_LOOKUPS_TOTAL.with_label_values(&["hit"]).inc();
+        return match cm {
+            Some(cm) => read(&cm),
+            None => Ok(None),
+        };
+    }
+    RECORD_CACHE_LOOKUPS_TOTAL
+        .with_label_values(&["miss"])
+        .inc();
+
+    let cm_api = ctx.cm_api();
+    match observe_api_call("configmaps", "get", cm_api.get(cm_name)).await {
+        Ok(cm) => read(&cm),
+        Err(kube::Error::Api(ErrorResponse { code: 404, .. })) => Ok(None),
+        Err(e) => {
+            ERRORS_TOTAL
+                .with_label_values(&["configmap", "get_error"])
+                .inc();
+            Err(Error::Kube(e))
+        }
+    }
+}
+
+/// Fetch the stored record of a node, if any. Records that were not
+/// migrated yet after a change of the storage layout or shard prefix length
+/// are still found.
+pub(crate) async fn get_record(ctx: &Context, node_name: &str) -> Result<Option<Record>> {
+    let config = ctx.config();
+    let prefix_length =
+        (config.storage_layout == StorageLayout::Sharded).then_some(config.shard_prefix_length);
+    if let Some(prefix_length) = prefix_length {
+        let shard = shard_name(node_name, prefix_length);
+        if let Some(record) =
+            fetch_record(ctx, &shard, |cm| shard_entry(ctx.keyring(), cm, node_name)).await?
+        {
+            return Ok(Some(record));
+        }
+    }
+
+    if let Some(record) = fetch_record(ctx, &configmap_name(node_name), |cm| {
+        node_record(ctx.keyring(), cm)
+    })
+    .await?
+    {
+        return Ok(Some(record));
+    }
+
+    for length in (1..=MAX_SHARD_PREFIX_LENGTH).filter(|l| Some(*l) != prefix_length) {
+        let shard = shard_name(node_name, length);
+        if let Some(record) =
+            fetch_record(ctx, &shard, |cm| shard_entry(ctx.keyring(), cm, node_name)).await?
+        {
+            return Ok(Some(record));
+        }
+    }
+    debug!(node = %node_name, record = %configmap_name(node_name), "No record found");
+    Ok(None)
+}
+
+/// Write the record of a node, replacing its taints and their expiry times
+/// and adding a snapshot of them to its history. Returns the number of
+/// taints stored, fewer than given when the last ones had to be dropped for
+/// the record to fit the size limit.
+pub(crate) async fn store_record(
+    ctx: &Context,
+    node_name: &str,
+    taints: &[Taint],
+    expiry: &BTreeMap<String, Time>,
+    reason: CaptureReason,
+) -> Result<usize> {
+    let config = ctx.config();
+    // Read live, as the record cache may not have seen the previous write
+    // of a node deleted twice in quick succession
+    let mut previous = Vec::new();
+    if config.history_length > 0 {
+        let cm_api = ctx.cm_api();
+        let existing = observe_api_call(
+            "configmaps",
+            "get",
+            cm_api.get_opt(&configmap_name(node_name)),
+        )
+        .await?;
+        if let Some(cm) = existing {
+            previous = node_record_history(ctx.keyring(), &cm);
+        }
+    }
+
+    match config.storage_layout {
+        StorageLayout::PerNode => {
+            let history = push_snapshot(previous, taints, expiry, reason, config.history_length);
+            store_node_record(ctx, node_name, taints, expiry, history).await
+        }
+        StorageLayout::Sharded => {
+            let shard = shard_name(node_name, config.shard_prefix_length);
+            let mut stored = taints.len();
+            update_shard(ctx, &shard, |data| {
+                let existing = data.get(&node_hash(node_name));
+                // Enforcement is set by operators, and kept across writes
+                let enforced = existing.is_some_and(|entry_json| entry_enforced(entry_json));
+                // A per-node record not migrated yet holds the history otherwise
+                let previous = match existing {
+                    Some(entry_json) => entry_history(ctx.keyring(), node_name, entry_json),
+                    None => previous.clone(),
+                };
+                let history =
+                    push_snapshot(previous, taints, expiry, reason, config.history_length);
+                let (entry_json, kept) =
+                    fit_entry(ctx.keyring(), node_name, taints, expiry, history, enforced)?;
+                data.insert(node_hash(node_name), entry_json);
+                stored = kept;
+                Ok(true)
+            })
+            .await?;
+            // The shard entry supersedes a per-node record left from before
+            // the migration
+            delete_node_record(ctx, node_name).await?;
+            Ok(stored)
+        }
+    }
+}
+
+/// Encode a shard entry, fitting the taints into the entry size limit first
+/// and the history, whose first snapshot holds the taints, into the space
+/// left. Returns the entry with the number of taints kept.
+fn fit_entry(
+    keyring: &Keyring,
+    node_name: &str,
+    taints: &[Taint],
+    expiry: &BTreeMap<String, Time>,
+    mut history: Vec<Snapshot>,
+    enforced: bool,
+) -> Result<(String, usize)> {
+    let (_, kept) = fit_prefix(taints, MAX_SHARD_ENTRY_BYTES, |taints| {
+        let entry_json = encode_entry(keyring, node_name, taints, expiry, &[], enforced)?;
+        Ok(((), entry_json.len()))
+    })?;
+    if let Some(current) = history.first_mut() {
+        current.taints.truncate(kept);
+    }
+    let (entry_json, _) = fit_prefix(&history, MAX_SHARD_ENTRY_BYTES, |history| {
+        let entry_json = encode_entry(
+            keyring,
+            node_name,
+            &taints[..kept],
+            expiry,
+            history,
+            enforced,
+        )?;
+        let size = entry_json.len();
+        Ok((entry_json, size))
+    })?;
+    Ok((entry_json, kept))
+}
+
+/// Snapshot history of a per-node record, starting over when it cannot be
+/// read
+fn node_record_history(keyring: &Keyring, cm: &ConfigMap) -> Vec<Snapshot> {
+    match node_record(keyring, cm) {
+        Ok(record) => record.map(|record| record.history).unwrap_or_default(),
+        Err(e) => {
+            warn!(record = %cm.name_any(), error = %e, "Starting a new history for unreadable record");
+            Vec::new()
+        }
+    }
+}
+
+/// Snapshot history of a shard entry, starting over when it cannot be read
+fn entry_history(keyring: &Keyring, node_name: &str, entry_json: &str) -> Vec<Snapshot> {
+    match parse_entry(keyring, entry_json) {
+        Ok(record) => record.history,
+        Err(e) => {
+            warn!(node = %node_name, error = %e, "Starting a new history for unreadable shard entry");
+            Vec::new()
+        }
+    }
+}
+
+/// Write the per-node ConfigMap of a node, returning the number of taints
+/// stored
+async fn store_node_record(
+    ctx: &Context,
+    node_name: &str,
+    taints: &[Taint],
+    expiry: &BTreeMap<String, Time>,
+    history: Vec<Snapshot>,
+) -> Result<usize> {
+    let (cm, stored) = encode_node_record(ctx, node_name, taints, expiry, history)?;
+    let patch_params = PatchParams::apply(SERVICE_NAME).force();
+    let cm_api = ctx.cm_api();
+    let written = observe_api_call(
+        "configmaps",
+        "patch",
+        cm_api.patch(&cm.name_any(), &patch_params, &Patch::Apply(&cm)),
+    )
+    .await
+    .map_err(|e| {
+        ERRORS_TOTAL
+            .with_label_values(&["configmap", "patch_error"])
+            .inc();
+        Error::Kube(e)
+    })?;
+    ctx.record_written(&written);
+
+    Ok(stored)
+}
+
+/// Change the per-node record in a ConfigMap with optimistic concurrency,
+/// like `update_shard`: the record is read, changed by `update` and written
+/// back with the ConfigMap's resourceVersion, and the whole update is
+/// retried when another writer got there first. Returns the number of
+/// taints stored, or None when there is no record or `update` returned false.
+async fn update_node_record(
+    ctx: &Context,
+    cm_name: &str,
+    mut update: impl FnMut(&mut Record) -> Result<bool>,
+) -> Result<Option<usize>> {
+    let cm_api = ctx.cm_api();
+    let patch_params = PatchParams::apply(SERVICE_NAME).force();
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let Some(cm) = observe_api_call("configmaps", "get", cm_api.get_opt(cm_name)).await? else {
+            return Ok(None);
+        };
+        let Some(mut record) = node_record(ctx.keyring(), &cm)? else {
+            return Ok(None);
+        };
+        if !update(&mut record)? {
+            return Ok(None);
+        }
+        let (mut updated, stored) = encode_node_record(
+            ctx,
+            &record.node,
+            &record.taints,
+            &record.expiry,
+            record.history,
+        )?;
+        updated.metadata.resource_version = cm.resource_version();
+        match observe_api_call(
+            "configmaps",
+            "patch",
+            cm_api.patch(cm_name, &patch_params, &Patch::Apply(&updated)),
+        )
+        .await
+        {
+            Ok(written) => {
+                ctx.record_written(&written);
+                return Ok(Some(stored));
+            }
+            Err(kube::Error::Api(ErrorResponse { code: 409, .. }))
+                if attempt < MAX_SHARD_WRITE_ATTEMPTS =>
+            {
+                debug!(record = %cm_name, attempt, "Record changed concurrently, retrying update");
+            }
+            Err(e) => {
+                ERRORS_TOTAL
+                    .with_label_values(&["configmap", "patch_error"])
+                    .inc();
+                return Err(Error::Kube(e));
+            }
+        }
+    }
+}
+
+/// Add the expiry times of taints that expired on a live node to its
+/// record, so an enforced record does not re-apply them. Times already
+/// stored are kept when earlier.
+pub(crate) async fn store_expired(
+    ctx: &Context,
+    node_name: &str,
+    expired: &BTreeMap<String, Time>,
+) -> Result<()> {
+    let config = ctx.config();
+    if config.storage_layout == StorageLayout::Sharded {
+        let shard = shard_name(node_name, config.shard_prefix_length);
+        let key = node_hash(node_name);
+        let mut found = false;
+        update_shard(ctx, &shard, |data| {
+            let Some(entry_json) = data.get(&key) else {
+                return Ok(false);
+            };
+            found = true;
+            let mut record = parse_entry(ctx.keyring(), entry_json)?;
+            if !merge_expired(&mut record, expired) {
+                return Ok(false);
+            }
+            let (entry_json, _) = fit_entry(
+                ctx.keyring(),
+                node_name,
+                &record.taints,
+                &record.expiry,
+                record.history,
+                record.enforced,
+            )?;
+            data.insert(key.clone(), entry_json);
+            Ok(true)
+        })
+        .await?;
+        if found {
+            return Ok(());
+        }
+    }
+    update_node_record(ctx, &configmap_name(node_name), |record| {
+        Ok(merge_expired(record, expired))
+    })
+    .await?;
+    Ok(())
+}
+
+/// Merge expiry times of stored taints into a record, returning whether
+/// it changed
+fn merge_expired(record: &mut Record, expired: &BTreeMap<String, Time>) -> bool {
+    let mut changed = false;
+    for (key, time) in expired {
+        let stored = record.taints.iter().any(|taint| &taint.key == key);
+        if stored
+            && record
+                .expiry
+                .get(key)
+                .is_none_or(|current| current.0 > time.0)
+        {
+            record.expiry.insert(key.clone(), time.clone());
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// Build the per-node ConfigMap of a node, returning it with the number of
+/// taints stored. The taints are fitted into the size limit first, and the
+/// history, whose first snapshot holds the taints, into the space left.
+fn encode_node_record(
+    ctx: &Context,
+    node_name: &str,
+    taints: &[Taint],
+    expiry: &BTreeMap<String, Time>,
+    mut history: Vec<Snapshot>,
+) -> Result<(ConfigMap, usize)> {
+    let cm_name = configmap_name(node_name);
+    let compression = ctx.config().record_compression;
+    let (payload, stored) = fit_prefix(taints, MAX_RECORD_BYTES, |taints| {
+        let taints_json = serde_json::to_string(taints).map_err(Error::Serialization)?;
+        let payload = encode_json(ctx.keyring(), node_name, taints_json, compression)?;
+        let size = payload.size();
+        Ok((payload, size))
+    })?;
+    if let Some(current) = history.first_mut() {
+        current.taints.truncate(stored);
+    }
+    // Expiry times are small, and stored in full for the taints kept
+    let expiry = expiry_of(&taints[..stored], expiry);
+    let expiry_json = serde_json::to_string(&expiry)?;
+    let expiry_payload = encode_json(ctx.keyring(), node_name, expiry_json, compression)?;
+    let history_limit = MAX_RECORD_BYTES
+        .saturating_sub(payload.size())
+        .saturating_sub(expiry_payload.size());
+    let (history_payload, kept_snapshots) = fit_prefix(&history, history_limit, |history| {
+        let history_json = serde_json::to_string(history)?;
+        let payload = encode_json(ctx.keyring(), node_name, history_json, compression)?;
+        let size = payload.size();
+        Ok((payload, size))
+    })?;
+    if kept_snapshots < history.len() {
+        debug!(
+            node = %node_name,
+            record = %cm_name,
+            "Dropped {} old snapshots to fit the size limit",
+            history.len() - kept_snapshots
+        );
+    }
+
+    // Always write ConfigMap, even if empty, to avoid restoring stale taints.
+    // Both maps are always applied, so switching between plain, compressed
+    // and encrypted storage removes the previous key.
+    let mut cm_data = BTreeMap::new();
+    let mut cm_binary_data = BTreeMap::new();
+    if stored > 0 {
+        payload.insert_into(JSON_STORAGE_KEY, &mut cm_data, &mut cm_binary_data);
+    }
+    if !expiry.is_empty() {
+        expiry_payload.insert_into(EXPIRY_STORAGE_KEY, &mut cm_data, &mut cm_binary_data);
+    }
+    if kept_snapshots > 0 {
+        history_payload.insert_into(HISTORY_STORAGE_KEY, &mut cm_data, &mut cm_binary_data);
+    }
+
+    let mut cm_annotations = BTreeMap::new();
+    cm_annotations.insert(CONFIGMAP_NODE_ANNOTATION.to_string(), node_name.to_string());
+
+    let cm = ConfigMap {
+        metadata: ObjectMeta {
+            name: Some(cm_name),
+            namespace: Some(ctx.config().configmap_namespace.clone()),
+            annotations: Some(cm_annotations),
+            labels: Some(record_labels(node_name)),
+            ..Default::default()
+        },
+        data: Some(cm_data),
+        binary_data: Some(cm_binary_data),
+        immutable: None,
+    };
+    Ok((cm, stored))
+}
+
+/// Delete the per-node ConfigMap of a node, if any
+async fn delete_node_record(ctx: &Context, node_name: &str) -> Result<()> {
+    let cm_api = ctx.cm_api();
+    match observe_api_call(
+        "configmaps",
+        "delete",
+        cm_api.delete(&configmap_name(node_name), &DeleteParams::default()),
+    )
+    .await
+    {
+        Ok(_) | Err(kube::Error::Api(ErrorResponse { code: 404, .. })) => Ok(()),
+        Err(e) => Err(Error::Kube(e)),
+    }
+}
+
+/// Labels of a shard ConfigMap
+fn shard_labels(shard: &str) -> BTreeMap<String, String> {
+    let prefix = shard.trim_start_matches(SHARD_NAME_PREFIX);
+    BTreeMap::from([
+        (MANAGED_BY_LABEL.to_string(), SERVICE_NAME.to_string()),
+        (COMPONENT_LABEL.to_string(), RECORD_COMPONENT.to_string()),
+        (SHARD_LABEL.to_string(), prefix.to_string()),
+    ])
+}
+
+/// Change the entries of a shard with optimistic concurrency: the shard is
+/// read, changed by `update` and written back with its resourceVersion, and
+/// the whole update is retried when another writer got there first. The
+/// shard is not written when `update` returns false.
+async fn update_shard(
+    ctx: &Context,
+    shard: &str,
+    mut update: impl FnMut(&mut BTreeMap<String, String>) -> Result<bool>,
+) -> Result<()> {
+    let cm_api = ctx.cm_api();
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let existing = observe_api_call("configmaps", "get", cm_api.get_opt(shard)).await?;
+        let mut cm = existing.clone().unwrap_or_else(|| ConfigMap {
+            metadata: ObjectMeta {
+                name: Some(shard.to_string()),
+                namespace: Some(ctx.config().configmap_namespace.clone()),
+                labels: Some(shard_labels(shard)),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let data = cm.data.get_or_insert_with(BTreeMap::new);
+        if !update(data)? {
+            return Ok(());
+        }
+        let size: usize = data.iter().map(|(k, v)| k.len() + v.len()).sum();
+        if size > MAX_SHARD_DATA_BYTES {
+            ERRORS_TOTAL
+                .with_label_values(&["configmap", "shard_full"])
+                .inc();
+            return Err(Error::ShardFull(shard.to_string()));
+        }
+
+        let result = match existing {
+            // The resourceVersion read above makes the replace fail on a
+            // concurrent write
+            Some(_) => {
+                observe_api_call(
+                    "configmaps",
+                    "replace",
+                    cm_api.replace(shard, &PostParams::default(), &cm),
+                )
+                .await
+            }
+            None => {
+                observe_api_call(
+                    "configmaps",
+                    "create",
+                    cm_api.create(&PostParams::default(), &cm),
+                )
+                .await
+            }
+        };
+        match result {
+            Ok(written) => {
+                ctx.record_written(&written);
+                return Ok(());
+            }
+            Err(kube::Error::Api(ErrorResponse { code: 409, .. }))
+                if attempt < MAX_SHARD_WRITE_ATTEMPTS =>
+            {
+                debug!(record = %shard, attempt, "Shard changed concurrently, retrying update");
+            }
+            Err(e) => {
+                ERRORS_TOTAL
+                    .with_label_values(&["configmap", "shard_write_error"])
+                    .inc();
+                return Err(Error::Kube(e));
+            }
+        }
+    }
+}
+
+/// Move records to where the configured layout and shard prefix length
+/// keep them, returning the nodes whose records were moved: per-node
+/// records and entries of shards of another prefix length into their
+/// shards, or shard entries into per-node records when switching back.
+/// Each record is copied unless a reconcile already wrote a newer one, then
+/// deleted, so reads find it in one place or the other throughout.
+pub async fn migrate_records(ctx: &Context) -> Result<Vec<String>> {
+    let config = ctx.config();
+    let cm_api = ctx.cm_api();
+    let mut migrated = Vec::new();
+    let records =
+        observe_api_call("configmaps", "list", cm_api.list(&record_list_params())).await?;
+    for cm in records {
+        let prefix = cm.labels().get(SHARD_LABEL);
+        match (config.storage_layout, prefix) {
+            (StorageLayout::Sharded, None) => {
+                migrated.extend(shard_node_record(ctx, &cm).await?);
+            }
+            (StorageLayout::Sharded, Some(prefix))
+                if prefix.len() != config.shard_prefix_length =>
+            {
+                migrated.extend(reshard_entries(ctx, &cm).await?);
+            }
+            (StorageLayout::PerNode, Some(_)) => {
+                migrated.extend(unshard_entries(ctx, &cm).await?);
+            }
+            _ => {}
+        }
+    }
+    Ok(migrated)
+}
+
+/// Move a per-node record into its shard
+async fn shard_node_record(ctx: &Context, cm: &ConfigMap) -> Result<Option<String>> {
+    let config = ctx.config();
+    let record = match node_record(ctx.keyring(), cm) {
+        Ok(Some(record)) => record,
+        Ok(None) => return Ok(None),
+        Err(e) => {
+            warn!(record = %cm.name_any(), error = %e, "Not migrating invalid record");
+            return Ok(None);
+        }
+    };
+
+    let shard = shard_name(&record.node, config.shard_prefix_length);
+    let (entry_json, kept) = fit_entry(
+        ctx.keyring(),
+        &record.node,
+        &record.taints,
+        &record.expiry,
+        record.history.clone(),
+        record.enforced,
+    )?;
+    if kept < record.taints.len() {
+        warn!(
+            node = %record.node,
+            record = %shard,
+            action = "truncate",
+            "Record exceeded the shard entry size limit, migrating only the first {} of {} taints",
+            kept,
+            record.taints.len()
+        );
+        RECORDS_TRUNCATED_TOTAL
+            .with_label_values(&[node_label(&config, &record.node)])
+            .inc();
+    }
+    update_shard(ctx, &shard, |data| {
+        let key = node_hash(&record.node);
+        if data.contains_key(&key) {
+            return Ok(false);
+        }
+        data.insert(key, entry_json.clone());
+        Ok(true)
+    })
+    .await?;
+    delete_node_record(ctx, &record.node).await?;
+    info!(node = %record.node, record = %shard, action = "migrate", "Moved record into its shard");
+    Ok(Some(record.node))
+}
+
+/// Move the entries of a shard of another prefix length into the shards of
+/// the configured one, then delete it. Entries are copied as stored, so
+/// encrypted ones are moved without decrypting them.
+async fn reshard_entries(ctx: &Context, cm: &ConfigMap) -> Result<Vec<String>> {
+    let prefix_length = ctx.config().shard_prefix_length;
+    let mut moved = Vec::new();
+    let mut complete = true;
+    for (key, entry_json) in cm.data.iter().flatten() {
+        let node_name = match serde_json::from_str::<Entry>(entry_json) {
+            Ok(entry) => entry.node,
+            Err(e) => {
+                warn!(record = %cm.name_any(), entry = %key, error = %e, "Not migrating invalid shard entry");
+                complete = false;
+                continue;
+            }
+        };
+        let shard = shard_name(&node_name, prefix_length);
+        update_shard(ctx, &shard, |data| {
+            if data.contains_key(key) {
+                return Ok(false);
+            }
+            data.insert(key.clone(), entry_json.clone());
+            Ok(true)
+        })
+        .await?;
+        info!(node = %node_name, record = %shard, action = "migrate", "Moved record into its shard");
+        moved.push(node_name);
+    }
+    if complete {
+        delete_migrated(ctx, cm).await?;
+    }
+    Ok(moved)
+}
+
+/// Move the entries of a shard into per-node records, then delete it
+async fn unshard_entries(ctx: &Context, cm: &ConfigMap) -> Result<Vec<String>> {
+    let cm_api = ctx.cm_api();
+    let mut moved = Vec::new();
+    let mut complete = true;
+    for (key, entry_json) in cm.data.iter().flatten() {
+        let record = match parse_entry(ctx.keyring(), entry_json) {
+            Ok(record) => record,
+            Err(e) => {
+                warn!(record = %cm.name_any(), entry = %key, error = %e, "Not migrating invalid shard entry");
+                complete = false;
+                continue;
+            }
+        };
+        let (mut node_cm, _) = encode_node_record(
+            ctx,
+            &record.node,
+            &record.taints,
+            &record.expiry,
+            record.history.clone(),
+        )?;
+        if record.enforced {
+            node_cm
+                .annotations_mut()
+                .insert(ENFORCED_RECORD_ANNOTATION.to_string(), "true".to_string());
+        }
+        // A per-node record that already exists was written by a cleanup
+        // since the switch, and is newer
+        match observe_api_call(
+            "configmaps",
+            "create",
+            cm_api.create(&PostParams::default(), &node_cm),
+        )
+        .await
+        {
+            Ok(_) | Err(kube::Error::Api(ErrorResponse { code: 409, .. })) => {}
+            Err(e) => return Err(Error::Kube(e)),
+        }
+        info!(node = %record.node, record = %node_cm.name_any(), action = "migrate", "Moved record out of its shard");
+        moved.push(record.node);
+    }
+    if complete {
+        delete_migrated(ctx, cm).await?;
+    }
+    Ok(moved)
+}
+
+/// Delete a shard whose entries were all moved, unless it changed since it
+/// was listed
+async fn delete_migrated(ctx: &Context, cm: &ConfigMap) -> Result<()> {
+    let params = DeleteParams {
+        preconditions: Some(Preconditions {
+            resource_version: cm.resource_version(),
+            uid: None,
+        }),
+        ..Default::default()
+    };
+    let cm_api = ctx.cm_api();
+    match observe_api_call(
+        "configmaps",
+        "delete",
+        cm_api.delete(&cm.name_any(), &params),
+    )
+    .await
+    {
+        Ok(_) | Err(kube::Error::Api(ErrorResponse { code: 404, .. })) => Ok(()),
+        Err(kube::Error::Api(ErrorResponse { code: 409, .. })) => {
+            debug!(record = %cm.name_any(), "Shard changed while it was migrated, keeping it");
+            Ok(())
+        }
+        Err(e) => Err(Error::Kube(e)),
+    }
+}
+
+/// Re-encrypt records not encrypted with the primary key, including ones
+/// written before encryption was enabled, returning the nodes whose records
+/// were rewritten. Once this completed, the other keys can be removed.
+pub async fn reseal_records(ctx: &Context) -> Result<Vec<String>> {
+    let keyring = ctx.keyring();
+    let Some(primary) = keyring.primary_id() else {
+        return Ok(Vec::new());
+    };
+
+    let cm_api = ctx.cm_api();
+    let mut resealed = Vec::new();
+    let records =
+        observe_api_call("configmaps", "list", cm_api.list(&record_list_params())).await?;
+    for cm in records {
+        let cm_name = cm.name_any();
+        if cm.labels().contains_key(SHARD_LABEL) {
+            let mut nodes = Vec::new();
+            update_shard(ctx, &cm_name, |data| {
+                nodes.clear();
+                for (key, entry_json) in data.iter_mut() {
+                    if entry_key_id(entry_json).as_deref() == Some(primary) {
+                        continue;
+                    }
+                    let record = match parse_entry(keyring, entry_json) {
+                        Ok(record) => record,
+                        Err(e) => {
+                            warn!(record = %cm_name, entry = %key, error = %e, "Not re-encrypting invalid shard entry");
+                            continue;
+                        }
+                    };
+                    *entry_json = encode_entry(
+                        keyring,
+                        &record.node,
+                        &record.taints,
+                        &record.expiry,
+                        &record.history,
+                        record.enforced,
+                    )?;
+                    nodes.push(record.node);
+                }
+                Ok(!nodes.is_empty())
+            })
+            .await?;
+            for node_name in &nodes {
+                info!(node = %node_name, record = %cm_name, action = "reseal", "Re-encrypted record");
+            }
+            resealed.extend(nodes);
+            continue;
+        }
+
+        if node_record_sealed_with(&cm, primary) {
+            continue;
+        }
+        let record = match node_record(keyring, &cm) {
+            // Empty records hold nothing to encrypt
+            Ok(Some(record)) if !record.taints.is_empty() || !record.history.is_empty() => record,
+            Ok(_) => continue,
+            Err(e) => {
+                warn!(record = %cm_name, error = %e, "Not re-encrypting invalid record");
+                continue;
+            }
+        };
+        // Re-read and written with its resourceVersion, so a cleanup since
+        // the list is not overwritten
+        let mut taint_count = 0;
+        let stored = update_node_record(ctx, &cm_name, |current| {
+            taint_count = current.taints.len();
+            Ok(true)
+        })
+        .await?;
+        let Some(stored) = stored else {
+            continue;
+        };
+        if stored < taint_count {
+            warn!(
+                node = %record.node,
+                record = %cm_name,
+                action = "truncate",
+                "Encrypted record exceeded the size limit, kept only the first {} of {} taints",
+                stored,
+                taint_count
+            );
+        }
+        info!(node = %record.node, record = %cm_name, action = "reseal", "Re-encrypted record");
+        resealed.push(record.node);
+    }
+    Ok(resealed)
+}
@@ -0,0 +1,133 @@
+use k8s_openapi::api::core::v1::{Node, Taint};
+use kube::ResourceExt;
+use std::collections::BTreeMap;
+use thiserror::Error;
+
+/// Maximum length of a taint value, like a label value
+const MAX_VALUE_LENGTH: usize = 63;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum TemplateError {
+    #[error("unterminated variable starting at offset {0}")]
+    Unterminated(usize),
+    #[error("unmatched '}}' at offset {0}, write '}}}}' for a literal brace")]
+    UnmatchedBrace(usize),
+    #[error("unknown variable '{0}', expected name, label:<key> or annotation:<key>")]
+    UnknownVariable(String),
+    #[error("node has no label {0}")]
+    MissingLabel(String),
+    #[error("node has no annotation {0}")]
+    MissingAnnotation(String),
+    #[error("rendered value '{0}' is not a valid taint value")]
+    InvalidValue(String),
+}
+
+/// Part of a parsed template
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Name,
+    Label(String),
+    Annotation(String),
+}
+
+/// Parse a taint value template. Variables are `{name}`, `{label:<key>}`
+/// and `{annotation:<key>}`; `{{` and `}}` are literal braces.
+fn parse(template: &str) -> Result<Vec<Segment>, TemplateError> {
+    let mut segments = Vec::new();
+    let mut literal = String::new();
+    let mut chars = template.char_indices().peekable();
+    while let Some((offset, c)) = chars.next() {
+        match c {
+            '{' if chars.next_if(|(_, c)| *c == '{').is_some() => literal.push('{'),
+            '}' if chars.next_if(|(_, c)| *c == '}').is_some() => literal.push('}'),
+            '}' => return Err(TemplateError::UnmatchedBrace(offset)),
+            '{' => {
+                let mut variable = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '}')) => break,
+                        Some((_, c)) => variable.push(c),
+                        None => return Err(TemplateError::Unterminated(offset)),
+                    }
+                }
+                if !literal.is_empty() {
+                    segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                }
+                segments.push(parse_variable(variable.trim())?);
+            }
+            c => literal.push(c),
+        }
+    }
+    if !literal.is_empty() {
+        segments.push(Segment::Literal(literal));
+    }
+    Ok(segments)
+}
+
+fn parse_variable(variable: &str) -> Result<Segment, TemplateError> {
+    match variable.split_once(':') {
+        None if variable == "name" => Ok(Segment::Name),
+        Some(("label", key)) if !key.is_empty() => Ok(Segment::Label(key.to_string())),
+        Some(("annotation", key)) if !key.is_empty() => Ok(Segment::Annotation(key.to_string())),
+        _ => Err(TemplateError::UnknownVariable(variable.to_string())),
+    }
+}
+
+/// Check that a template parses, without rendering it
+pub fn validate(template: &str) -> Result<(), TemplateError> {
+    parse(template).map(|_| ())
+}
+
+/// Render a taint value template with the name, labels and annotations of
+/// a node. The result must be a valid taint value.
+pub fn render(template: &str, node: &Node) -> Result<String, TemplateError> {
+    let mut value = String::new();
+    for segment in parse(template)? {
+        match segment {
+            Segment::Literal(literal) => value.push_str(&literal),
+            Segment::Name => value.push_str(&node.name_any()),
+            Segment::Label(key) => value.push_str(
+                node.labels()
+                    .get(&key)
+                    .ok_or(TemplateError::MissingLabel(key))?,
+            ),
+            Segment::Annotation(key) => value.push_str(
+                node.annotations()
+                    .get(&key)
+                    .ok_or(TemplateError::MissingAnnotation(key))?,
+            ),
+        }
+    }
+    if !is_valid_value(&value) {
+        return Err(TemplateError::InvalidValue(value));
+    }
+    Ok(value)
+}
+
+/// Whether a taint value is valid: empty, or at most 63 alphanumerics,
+/// '-', '_' and '.', starting and ending with an alphanumeric
+fn is_valid_value(value: &str) -> bool {
+    let alphanumeric_ends = value.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && value.ends_with(|c: char| c.is_ascii_alphanumeric());
+    value.is_empty()
+        || (value.len() <= MAX_VALUE_LENGTH
+            && alphanumeric_ends
+            && value
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')))
+}
+
+/// Give a taint the value rendered from the template configured for its
+/// key, if any
+pub fn render_taint(
+    mut taint: Taint,
+    templates: &BTreeMap<String, String>,
+    node: &Node,
+) -> Result<Taint, TemplateError> {
+    if let Some(template) = templates.get(&taint.key) {
+        let value = render(template, node)?;
+        taint.value = (!value.is_empty()).then_some(value);
+    }
+    Ok(taint)
+}
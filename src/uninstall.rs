@@ -0,0 +1,82 @@
+use crate::{
+    remove_finalizer,
+    storage::{is_record_configmap, records_in},
+    Context, Result,
+};
+use k8s_openapi::api::core::v1::Node;
+use kube::api::{Api, DeleteParams, ListParams, PatchParams, ResourceExt};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use tracing::{info, warn};
+
+/// What an uninstall changed, or would change in a dry run
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct UninstallReport {
+    /// Nodes our finalizer and restore annotation were removed from
+    pub nodes_released: Vec<String>,
+    /// Names of the nodes whose records were deleted
+    pub records_deleted: Vec<String>,
+    /// Nodes that could not be released, with the error
+    pub nodes_failed: BTreeMap<String, String>,
+}
+
+/// Remove our finalizer and restore annotation from every node within the
+/// configured node selectors, and optionally delete all records.
+/// Nodes that failed to be released are listed in the report.
+/// The controller must be stopped first, or it adds the finalizer back.
+pub async fn uninstall(
+    ctx: &Context,
+    delete_records: bool,
+    dry_run: bool,
+) -> Result<UninstallReport> {
+    let mut report = UninstallReport::default();
+    let node_api: Api<Node> = Api::all(ctx.client.clone());
+
+    let patch_params = PatchParams {
+        dry_run,
+        ..Default::default()
+    };
+    // A node that cannot be released does not keep the others from being
+    // released, and is reported at the end
+    for node in node_api.list(&ctx.node_list_params()).await? {
+        let node_name = node.name_any();
+        match remove_finalizer(&node_api, node, &patch_params, true).await {
+            Ok(true) => {
+                info!(node = %node_name, action = "remove_finalizer", dry_run, "Released node");
+                report.nodes_released.push(node_name);
+            }
+            Ok(false) => {}
+            Err(e) => {
+                warn!(node = %node_name, error = ?e, "Failed to release node");
+                report.nodes_failed.insert(node_name, e.to_string());
+            }
+        }
+    }
+
+    if delete_records {
+        let delete_params = DeleteParams {
+            dry_run,
+            ..Default::default()
+        };
+        // Records are found by annotation or shard label, so ones never
+        // adopted and labeled by a running controller are deleted too
+        for cm in ctx.cm_api().list(&ListParams::default()).await? {
+            if !is_record_configmap(&cm) {
+                continue;
+            }
+            ctx.cm_api().delete(&cm.name_any(), &delete_params).await?;
+            for record in records_in(ctx.keyring(), &cm) {
+                info!(
+                    node = %record.node,
+                    record = %cm.name_any(),
+                    action = "delete_record",
+                    dry_run,
+                    "Deleted record"
+                );
+                report.records_deleted.push(record.node);
+            }
+        }
+    }
+
+    Ok(report)
+}
@@ -0,0 +1,92 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use super::common::{node, restore_node, store_node, FakeApi};
+    use http::Method;
+    use kube::ResourceExt;
+    use node_taint_preserver::{
+        breaker::{resume, sync_breaker_state, BREAKER_CONFIGMAP_NAME},
+        Config, Context,
+    };
+    use std::sync::Arc;
+
+    const RESUME_ANNOTATION: &str = "nodetaintpreserver.example.com/resume";
+
+    fn setup() -> (FakeApi, Arc<Context>) {
+        let api = FakeApi::new();
+        let config = Config {
+            circuit_breaker_max_cleanups: 2,
+            ..Default::default()
+        };
+        let ctx = Arc::new(Context::new(api.client(), config));
+        (api, ctx)
+    }
+
+    /// Delete nodes with a custom taint, leaving their records behind
+    async fn store_nodes(api: &FakeApi, ctx: &Arc<Context>, names: &[&str]) {
+        for name in names {
+            store_node(api, ctx, &node(name, &[("dedicated", "gpu", "NoSchedule")])).await;
+        }
+    }
+
+    /// Test 1: Mass deletions trip the breaker, which pauses restores until
+    /// the resume annotation is set
+    #[tokio::test]
+    async fn test_breaker_trips_and_resumes() {
+        let (api, ctx) = setup();
+        store_nodes(&api, &ctx, &["worker-1", "worker-2", "worker-3"]).await;
+        assert!(api.configmap("default", BREAKER_CONFIGMAP_NAME).is_some());
+        assert!(api.has_event("MassDeletionDetected"));
+
+        assert!(restore_node(&api, &ctx, "worker-1").await.is_empty());
+        // Syncing with the persisted state keeps the breaker open
+        sync_breaker_state(&ctx).await.unwrap();
+        assert!(restore_node(&api, &ctx, "worker-1").await.is_empty());
+
+        let mut breaker = api.configmap("default", BREAKER_CONFIGMAP_NAME).unwrap();
+        breaker
+            .annotations_mut()
+            .insert(RESUME_ANNOTATION.to_string(), "true".to_string());
+        api.add_configmap("default", &breaker);
+        sync_breaker_state(&ctx).await.unwrap();
+        assert!(api.configmap("default", BREAKER_CONFIGMAP_NAME).is_none());
+        assert!(api.has_event("RestoresResumed"));
+        assert_eq!(restore_node(&api, &ctx, "worker-1").await, ["dedicated"]);
+    }
+
+    /// Test 2: The breaker only opens once its state is persisted, and a
+    /// failed trip is retried on the next cleanup
+    #[tokio::test]
+    async fn test_breaker_persist_failure() {
+        let (api, ctx) = setup();
+        api.fail_object(Method::PATCH, "configmaps", BREAKER_CONFIGMAP_NAME, 500);
+        store_nodes(&api, &ctx, &["worker-1", "worker-2", "worker-3"]).await;
+        assert!(api.configmap("default", BREAKER_CONFIGMAP_NAME).is_none());
+        assert!(!api.has_event("MassDeletionDetected"));
+
+        // A sync finding no ConfigMap agrees with the closed breaker
+        sync_breaker_state(&ctx).await.unwrap();
+        assert_eq!(restore_node(&api, &ctx, "worker-1").await, ["dedicated"]);
+
+        store_nodes(&api, &ctx, &["worker-4"]).await;
+        assert!(api.configmap("default", BREAKER_CONFIGMAP_NAME).is_some());
+        sync_breaker_state(&ctx).await.unwrap();
+        assert!(restore_node(&api, &ctx, "worker-2").await.is_empty());
+    }
+
+    /// Test 3: Resuming deletes the breaker's ConfigMap, which closes the
+    /// breaker on the next sync
+    #[tokio::test]
+    async fn test_breaker_resume_command() {
+        let (api, ctx) = setup();
+        assert!(!resume(&ctx).await.unwrap());
+        store_nodes(&api, &ctx, &["worker-1", "worker-2", "worker-3"]).await;
+
+        assert!(resume(&ctx).await.unwrap());
+        assert!(restore_node(&api, &ctx, "worker-1").await.is_empty());
+        sync_breaker_state(&ctx).await.unwrap();
+        assert_eq!(restore_node(&api, &ctx, "worker-1").await, ["dedicated"]);
+        assert!(!resume(&ctx).await.unwrap());
+    }
+}
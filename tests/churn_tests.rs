@@ -0,0 +1,223 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use super::common::{node, FakeApi};
+    use k8s_openapi::api::core::v1::{Node, Taint};
+    use kube::ResourceExt;
+    use node_taint_preserver::{reconcile, Config, Context};
+    use proptest::prelude::*;
+    use std::{collections::BTreeMap, sync::Arc};
+
+    const NODES: &[&str] = &["worker-0", "worker-1", "worker-2"];
+    const KEYS: &[&str] = &[
+        "dedicated",
+        "team",
+        "example.com/pool",
+        "node.kubernetes.io/unreachable",
+        "node-role.kubernetes.io/control-plane",
+        "CriticalAddonsOnly",
+    ];
+    const VALUES: &[&str] = &["", "a", "b"];
+    const EFFECTS: &[&str] = &["NoSchedule", "PreferNoSchedule", "NoExecute"];
+    /// Reconciles after which a deleted node must be gone
+    const MAX_CLEANUP_RECONCILES: usize = 5;
+
+    #[derive(Clone, Debug)]
+    enum Op {
+        Create { node: usize, taints: Vec<Taint> },
+        EditTaints { node: usize, taints: Vec<Taint> },
+        Delete { node: usize },
+        ForceDelete { node: usize },
+        RestartController,
+    }
+
+    fn taint_strategy() -> impl Strategy<Value = Taint> {
+        (
+            prop::sample::select(KEYS),
+            prop::sample::select(VALUES),
+            prop::sample::select(EFFECTS),
+        )
+            .prop_map(|(key, value, effect)| Taint {
+                key: key.to_string(),
+                value: Some(value.to_string()),
+                effect: effect.to_string(),
+                time_added: None,
+            })
+    }
+
+    /// Taints with unique keys, like the API server enforces per effect
+    fn taints_strategy() -> impl Strategy<Value = Vec<Taint>> {
+        prop::collection::vec(taint_strategy(), 0..4).prop_map(|taints| {
+            let mut unique: Vec<Taint> = Vec::new();
+            for taint in taints {
+                if !unique.iter().any(|t| t.key == taint.key) {
+                    unique.push(taint);
+                }
+            }
+            unique
+        })
+    }
+
+    fn op_strategy() -> impl Strategy<Value = Op> {
+        let node = 0..NODES.len();
+        prop_oneof![
+            3 => (node.clone(), taints_strategy()).prop_map(|(node, taints)| Op::Create { node, taints }),
+            2 => (node.clone(), taints_strategy()).prop_map(|(node, taints)| Op::EditTaints { node, taints }),
+            3 => node.clone().prop_map(|node| Op::Delete { node }),
+            1 => node.prop_map(|node| Op::ForceDelete { node }),
+            1 => Just(Op::RestartController),
+        ]
+    }
+
+    /// Independent oracle of the taints that must never be stored or restored
+    fn is_protected(taint: &Taint) -> bool {
+        taint.key == "CriticalAddonsOnly"
+            || ["node.kubernetes.io/", "node-role.kubernetes.io/"]
+                .iter()
+                .any(|prefix| taint.key.starts_with(prefix))
+    }
+
+    fn node_taints(node: &Node) -> Vec<Taint> {
+        node.spec
+            .as_ref()
+            .and_then(|spec| spec.taints.clone())
+            .unwrap_or_default()
+    }
+
+    fn sorted(mut taints: Vec<Taint>) -> Vec<Taint> {
+        taints.sort_by(|a, b| a.key.cmp(&b.key));
+        taints
+    }
+
+    /// Stored taints of every record, by node name
+    fn stored_records(api: &FakeApi) -> BTreeMap<String, Vec<Taint>> {
+        api.configmaps("default")
+            .into_iter()
+            .map(|cm| {
+                let node_name =
+                    cm.annotations()["nodetaintpreserver.example.com/node-name"].clone();
+                let taints = cm
+                    .data
+                    .as_ref()
+                    .and_then(|data| data.get("preserved_taints_json"))
+                    .map(|json| serde_json::from_str(json).unwrap())
+                    .unwrap_or_default();
+                (node_name, taints)
+            })
+            .collect()
+    }
+
+    /// Reconcile a node until it is gone or the limit is reached
+    async fn reconcile_times(api: &FakeApi, ctx: &Arc<Context>, name: &str, times: usize) {
+        for _ in 0..times {
+            let Some(node) = api.node(name) else {
+                return;
+            };
+            reconcile(Arc::new(node), ctx.clone()).await.unwrap();
+        }
+    }
+
+    /// Apply a sequence of operations, checking the invariants after each
+    async fn simulate(ops: Vec<Op>) -> Result<(), TestCaseError> {
+        let api = FakeApi::new();
+        let mut ctx = Arc::new(Context::new(api.client(), Config::default()));
+        // Taints each node must have in its record after a graceful delete
+        let mut expected_records: BTreeMap<String, Vec<Taint>> = BTreeMap::new();
+
+        for op in ops {
+            match op {
+                Op::Create { node: i, taints } => {
+                    let name = NODES[i];
+                    if api.node(name).is_some() {
+                        continue;
+                    }
+                    api.add_node(&node(name, &[]));
+                    api.set_node_taints(name, &taints);
+                    reconcile_times(&api, &ctx, name, 2).await;
+
+                    // Stored taints are added by key, never overwriting live ones
+                    let mut expected = taints.clone();
+                    for taint in expected_records.get(name).cloned().unwrap_or_default() {
+                        if !expected.iter().any(|t| t.key == taint.key) {
+                            expected.push(taint);
+                        }
+                    }
+                    let live = node_taints(&api.node(name).unwrap());
+                    prop_assert_eq!(sorted(live.clone()), sorted(expected));
+                    for taint in live.iter().filter(|t| is_protected(t)) {
+                        prop_assert!(
+                            taints.contains(taint),
+                            "protected taint restored: {:?}",
+                            taint
+                        );
+                    }
+                }
+                Op::EditTaints { node: i, taints } => {
+                    let name = NODES[i];
+                    if api.node(name).is_none() {
+                        continue;
+                    }
+                    api.set_node_taints(name, &taints);
+                    reconcile_times(&api, &ctx, name, 1).await;
+                    let live = node_taints(&api.node(name).unwrap());
+                    prop_assert_eq!(sorted(live), sorted(taints));
+                }
+                Op::Delete { node: i } => {
+                    let name = NODES[i];
+                    let Some(node) = api.node(name) else {
+                        continue;
+                    };
+                    let custom: Vec<Taint> = node_taints(&node)
+                        .into_iter()
+                        .filter(|t| !is_protected(t))
+                        .collect();
+                    api.delete_node(name);
+                    reconcile_times(&api, &ctx, name, MAX_CLEANUP_RECONCILES).await;
+                    prop_assert!(
+                        api.node(name).is_none(),
+                        "finalizer of {} was not removed",
+                        name
+                    );
+                    expected_records.insert(name.to_string(), custom);
+                }
+                Op::ForceDelete { node: i } => api.force_delete_node(NODES[i]),
+                Op::RestartController => {
+                    ctx = Arc::new(Context::new(api.client(), Config::default()));
+                }
+            }
+
+            let stored = stored_records(&api);
+            for (name, taints) in &stored {
+                prop_assert!(
+                    !taints.iter().any(is_protected),
+                    "protected taint stored for {}: {:?}",
+                    name,
+                    taints
+                );
+            }
+            for (name, expected) in &expected_records {
+                let record = stored.get(name).cloned();
+                prop_assert_eq!(
+                    record.map(sorted),
+                    Some(sorted(expected.clone())),
+                    "record of {} lost",
+                    name
+                );
+            }
+        }
+        Ok(())
+    }
+
+    proptest! {
+        /// Test 1: Random node churn never breaks the invariants
+        #[test]
+        fn test_node_churn(ops in prop::collection::vec(op_strategy(), 1..25)) {
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap()
+                .block_on(simulate(ops))?;
+        }
+    }
+}
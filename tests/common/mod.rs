@@ -0,0 +1,713 @@
+//! In-memory fake of the Kubernetes API, served as a `tower::Service` behind
+//! a `kube::Client`, so reconciles can be tested without a cluster.
+//!
+//! It keeps objects as JSON and implements the subset of API semantics the
+//! controller relies on: get/list/create/replace/delete, JSON, merge and
+//! apply patches, resourceVersion preconditions, finalizers blocking deletion and
+//! server-side dry run. Server-side apply is approximated by a merge patch
+//! in which `data`, `binaryData` and `spec.taints` are replaced wholesale.
+//! Watches never send an event, so reflectors only see their initial list.
+#![allow(dead_code)]
+
+use bytes::Bytes;
+use futures::{future, stream, StreamExt};
+use http::{Method, Request, Response, StatusCode};
+use http_body::Frame;
+use http_body_util::{BodyExt, Either, Full, StreamBody};
+use k8s_openapi::{
+    api::{
+        core::v1::{ConfigMap, Node, Taint},
+        events::v1::Event,
+    },
+    chrono::{SecondsFormat, Utc},
+};
+use kube::{runtime::controller::Action, Client, ResourceExt};
+use node_taint_preserver::{reconcile, Context, Error};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::{
+    collections::BTreeMap,
+    convert::Infallible,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// Resources the fake API serves, with the kind of their objects
+const RESOURCES: &[(&str, &str)] = &[
+    ("nodes", "Node"),
+    ("configmaps", "ConfigMap"),
+    ("secrets", "Secret"),
+    ("events", "Event"),
+];
+
+/// A request failure injected with [`FakeApi::fail`]
+struct Failure {
+    method: Method,
+    resource: String,
+    /// Only requests for this object fail, if set
+    name: Option<String>,
+    code: u16,
+}
+
+#[derive(Default)]
+struct State {
+    /// Objects by collection path (e.g. `api/v1/namespaces/default/configmaps`) and name
+    objects: BTreeMap<(String, String), Value>,
+    resource_version: u64,
+    failures: Vec<Failure>,
+    /// Requests that never get a response, by method and resource
+    stalls: Vec<(Method, String)>,
+    requests: Vec<(Method, String)>,
+    /// Query strings of the requests, by path
+    queries: Vec<(String, String)>,
+}
+
+impl State {
+    fn next_resource_version(&mut self) -> String {
+        self.resource_version += 1;
+        self.resource_version.to_string()
+    }
+}
+
+/// In-memory Kubernetes API
+#[derive(Clone, Default)]
+pub struct FakeApi {
+    state: Arc<Mutex<State>>,
+}
+
+impl FakeApi {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Client sending every request to this fake API
+    pub fn client(&self) -> Client {
+        let state = self.state.clone();
+        let service = tower::service_fn(move |request: Request<kube::client::Body>| {
+            let state = state.clone();
+            async move {
+                let (parts, body) = request.into_parts();
+                let body = body.collect().await.unwrap().to_bytes();
+                let stalled = state
+                    .lock()
+                    .unwrap()
+                    .stalls
+                    .iter()
+                    .any(|(method, resource)| {
+                        *method == parts.method
+                            && parts.uri.path().split('/').any(|s| s == resource)
+                    });
+                if stalled {
+                    future::pending::<()>().await;
+                }
+                let response = Response::builder().header("content-type", "application/json");
+                let watch = parts
+                    .uri
+                    .query()
+                    .unwrap_or_default()
+                    .split('&')
+                    .any(|p| p == "watch=true");
+                let response = if watch {
+                    let path = parts.uri.path().trim_matches('/').to_string();
+                    let query = parts.uri.query().unwrap_or_default().to_string();
+                    let mut state = state.lock().unwrap();
+                    state.requests.push((parts.method.clone(), path.clone()));
+                    state.queries.push((path, query));
+                    response.body(Either::Right(StreamBody::new(stream::pending::<
+                        Result<Frame<Bytes>, Infallible>,
+                    >())))
+                } else {
+                    let (status, value) = handle(&state, &parts, &body);
+                    response
+                        .status(status)
+                        .body(Either::Left(Full::new(Bytes::from(value.to_string()))))
+                }
+                .unwrap();
+                Ok::<_, Infallible>(response)
+            }
+        });
+        Client::new(service, "default")
+    }
+
+    /// Make the next request with this method to this resource fail
+    pub fn fail(&self, method: Method, resource: &str, code: u16) {
+        self.state.lock().unwrap().failures.push(Failure {
+            method,
+            resource: resource.to_string(),
+            name: None,
+            code,
+        });
+    }
+
+    /// Make the next request with this method to this object fail
+    pub fn fail_object(&self, method: Method, resource: &str, name: &str, code: u16) {
+        self.state.lock().unwrap().failures.push(Failure {
+            method,
+            resource: resource.to_string(),
+            name: Some(name.to_string()),
+            code,
+        });
+    }
+
+    /// Make every request with this method to this resource hang, as on an
+    /// unresponsive API server
+    pub fn stall(&self, method: Method, resource: &str) {
+        self.state
+            .lock()
+            .unwrap()
+            .stalls
+            .push((method, resource.to_string()));
+    }
+
+    /// Requests received so far, as method and path
+    pub fn requests(&self) -> Vec<(Method, String)> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    /// Label and field selectors of the requests to a path, decoded
+    pub fn selectors(&self, path: &str) -> Vec<(String, String)> {
+        let param = |query: &str, name: &str| {
+            query
+                .split('&')
+                .find_map(|p| p.strip_prefix(name)?.strip_prefix('='))
+                .map(percent_decode)
+                .unwrap_or_default()
+        };
+        self.state
+            .lock()
+            .unwrap()
+            .queries
+            .iter()
+            .filter(|(p, _)| p == path)
+            .map(|(_, query)| (param(query, "labelSelector"), param(query, "fieldSelector")))
+            .collect()
+    }
+
+    fn create<K: serde::Serialize>(&self, collection: &str, object: &K) {
+        let mut state = self.state.lock().unwrap();
+        let mut value = serde_json::to_value(object).unwrap();
+        let name = value["metadata"]["name"].as_str().unwrap().to_string();
+        initialize_metadata(&mut state, &mut value);
+        state.objects.insert((collection.to_string(), name), value);
+    }
+
+    fn get<K: DeserializeOwned>(&self, collection: &str, name: &str) -> Option<K> {
+        let state = self.state.lock().unwrap();
+        state
+            .objects
+            .get(&(collection.to_string(), name.to_string()))
+            .map(|value| serde_json::from_value(value.clone()).unwrap())
+    }
+
+    fn list<K: DeserializeOwned>(&self, collection: &str) -> Vec<K> {
+        let state = self.state.lock().unwrap();
+        state
+            .objects
+            .iter()
+            .filter(|((c, _), _)| c == collection)
+            .map(|(_, value)| serde_json::from_value(value.clone()).unwrap())
+            .collect()
+    }
+
+    /// Create a node, replacing any node of the same name
+    pub fn add_node(&self, node: &Node) {
+        self.create("api/v1/nodes", node);
+    }
+
+    pub fn node(&self, name: &str) -> Option<Node> {
+        self.get("api/v1/nodes", name)
+    }
+
+    /// Delete a node like `kubectl delete node` would
+    pub fn delete_node(&self, name: &str) {
+        let mut state = self.state.lock().unwrap();
+        delete(&mut state, "api/v1/nodes", name).unwrap();
+    }
+
+    /// Replace the taints of a live node, like `kubectl taint` would
+    pub fn set_node_taints(&self, name: &str, taints: &[Taint]) {
+        let mut state = self.state.lock().unwrap();
+        let resource_version = state.next_resource_version();
+        let node = state
+            .objects
+            .get_mut(&("api/v1/nodes".to_string(), name.to_string()))
+            .expect("node should exist");
+        node["spec"]["taints"] = serde_json::to_value(taints).unwrap();
+        node["metadata"]["resourceVersion"] = json!(resource_version);
+    }
+
+    /// Remove a node immediately, ignoring its finalizers
+    pub fn force_delete_node(&self, name: &str) {
+        let mut state = self.state.lock().unwrap();
+        state
+            .objects
+            .remove(&("api/v1/nodes".to_string(), name.to_string()));
+    }
+
+    pub fn add_configmap(&self, namespace: &str, cm: &ConfigMap) {
+        self.create(&configmap_collection(namespace), cm);
+    }
+
+    pub fn configmap(&self, namespace: &str, name: &str) -> Option<ConfigMap> {
+        self.get(&configmap_collection(namespace), name)
+    }
+
+    pub fn configmaps(&self, namespace: &str) -> Vec<ConfigMap> {
+        self.list(&configmap_collection(namespace))
+    }
+
+    /// Keys of the taints of a live node
+    pub fn node_taint_keys(&self, name: &str) -> Vec<String> {
+        taint_keys(&node_taints(&self.node(name).expect("node should exist")))
+    }
+
+    /// Whether an Event with this reason was emitted
+    pub fn has_event(&self, reason: &str) -> bool {
+        self.events()
+            .iter()
+            .any(|e| e.reason.as_deref() == Some(reason))
+    }
+
+    /// Events of all namespaces
+    pub fn events(&self) -> Vec<Event> {
+        let state = self.state.lock().unwrap();
+        state
+            .objects
+            .iter()
+            .filter(|((collection, _), _)| collection.ends_with("/events"))
+            .map(|(_, value)| serde_json::from_value(value.clone()).unwrap())
+            .collect()
+    }
+}
+
+fn configmap_collection(namespace: &str) -> String {
+    format!("api/v1/namespaces/{}/configmaps", namespace)
+}
+
+/// Node with the given name and taints
+pub fn node(name: &str, taints: &[(&str, &str, &str)]) -> Node {
+    let mut node = Node::default();
+    node.metadata.name = Some(name.to_string());
+    let taints: Vec<Taint> = taints
+        .iter()
+        .map(|(key, value, effect)| Taint {
+            key: key.to_string(),
+            value: Some(value.to_string()),
+            effect: effect.to_string(),
+            time_added: None,
+        })
+        .collect();
+    node.spec = Some(k8s_openapi::api::core::v1::NodeSpec {
+        taints: Some(taints),
+        ..Default::default()
+    });
+    node
+}
+
+pub fn node_taints(node: &Node) -> Vec<Taint> {
+    node.spec
+        .as_ref()
+        .and_then(|spec| spec.taints.clone())
+        .unwrap_or_default()
+}
+
+pub fn taint_keys(taints: &[Taint]) -> Vec<String> {
+    taints.iter().map(|t| t.key.clone()).collect()
+}
+
+/// Reconcile the current state of a node
+pub async fn try_reconcile_node(
+    api: &FakeApi,
+    ctx: &Arc<Context>,
+    name: &str,
+) -> Result<Action, Error> {
+    let node = api.node(name).expect("node should exist");
+    reconcile(Arc::new(node), ctx.clone()).await
+}
+
+/// Reconcile the current state of a node, which must succeed
+pub async fn reconcile_node(api: &FakeApi, ctx: &Arc<Context>, name: &str) -> Action {
+    try_reconcile_node(api, ctx, name).await.unwrap()
+}
+
+/// Run a node through its first reconcile, deletion and cleanup, leaving
+/// its record behind
+pub async fn store_node(api: &FakeApi, ctx: &Arc<Context>, node: &Node) {
+    let name = node.name_any();
+    api.add_node(node);
+    reconcile_node(api, ctx, &name).await;
+    api.delete_node(&name);
+    reconcile_node(api, ctx, &name).await;
+    assert!(api.node(&name).is_none(), "node should be released");
+}
+
+/// Recreate a node without taints and return its taint keys once restored
+pub async fn restore_node(api: &FakeApi, ctx: &Arc<Context>, name: &str) -> Vec<String> {
+    api.add_node(&node(name, &[]));
+    reconcile_node(api, ctx, name).await;
+    reconcile_node(api, ctx, name).await;
+    api.node_taint_keys(name)
+}
+
+/// Start the record watch of a context, tracking the watched records like
+/// the controller does, and wait until its cache synced
+pub async fn sync_record_cache(ctx: &Arc<Context>) {
+    let watched = ctx.clone();
+    tokio::spawn(ctx.watch_records().for_each(move |cm| {
+        if let Ok(cm) = cm {
+            watched.observe_record(&cm);
+        }
+        async {}
+    }));
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !ctx.record_cache_synced() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("record cache should sync");
+}
+
+fn status(code: u16, reason: &str, message: &str) -> (StatusCode, Value) {
+    (
+        StatusCode::from_u16(code).unwrap(),
+        json!({
+            "kind": "Status",
+            "apiVersion": "v1",
+            "metadata": {},
+            "status": "Failure",
+            "message": message,
+            "reason": reason,
+            "code": code,
+        }),
+    )
+}
+
+fn initialize_metadata(state: &mut State, value: &mut Value) {
+    let resource_version = state.next_resource_version();
+    let metadata = value["metadata"].as_object_mut().unwrap();
+    metadata.insert(
+        "uid".to_string(),
+        json!(format!("uid-{}", resource_version)),
+    );
+    metadata.insert("resourceVersion".to_string(), json!(resource_version));
+    metadata.insert(
+        "creationTimestamp".to_string(),
+        json!(Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)),
+    );
+}
+
+/// Handle one API request against the state
+fn handle(state: &Mutex<State>, parts: &http::request::Parts, body: &[u8]) -> (StatusCode, Value) {
+    let mut state = state.lock().unwrap();
+    let path = parts.uri.path().trim_matches('/').to_string();
+    let query = parts.uri.query().unwrap_or_default();
+    state.requests.push((parts.method.clone(), path.clone()));
+    state.queries.push((path.clone(), query.to_string()));
+
+    let segments: Vec<&str> = path.split('/').collect();
+    let Some(index) = segments
+        .iter()
+        .rposition(|s| RESOURCES.iter().any(|(r, _)| r == s))
+    else {
+        return status(404, "NotFound", "unknown resource");
+    };
+    let resource = segments[index];
+    let collection = segments[..=index].join("/");
+    let name = segments.get(index + 1).map(|s| s.to_string());
+    let namespace = segments
+        .iter()
+        .position(|s| *s == "namespaces")
+        .and_then(|i| segments.get(i + 1))
+        .map(|s| s.to_string());
+
+    if let Some(i) = state.failures.iter().position(|f| {
+        f.method == parts.method && f.resource == resource && (f.name.is_none() || f.name == name)
+    }) {
+        let failure = state.failures.remove(i);
+        return status(failure.code, "InternalError", "injected failure");
+    }
+
+    // Server-side dry run works on a copy of the objects that is discarded.
+    // Deletes carry it in their DeleteOptions body.
+    let dry_run = query.split('&').any(|p| p == "dryRun=All")
+        || (parts.method == Method::DELETE
+            && serde_json::from_slice::<Value>(body)
+                .is_ok_and(|options| options["dryRun"] == json!(["All"])));
+    let saved = dry_run.then(|| (state.objects.clone(), state.resource_version));
+    let result = match (&parts.method, name) {
+        (&Method::GET, Some(name)) => get(&state, &collection, &name),
+        (&Method::GET, None) => Ok(list(&state, &collection, resource, query)),
+        (&Method::POST, None) => create(&mut state, &collection, namespace, body),
+        (&Method::PATCH, Some(name)) => {
+            let content_type = parts
+                .headers
+                .get("content-type")
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default();
+            patch(
+                &mut state,
+                &collection,
+                &name,
+                namespace,
+                content_type,
+                body,
+            )
+        }
+        (&Method::PUT, Some(name)) => replace(&mut state, &collection, &name, body),
+        (&Method::DELETE, Some(name)) => delete(&mut state, &collection, &name),
+        _ => Err(status(405, "MethodNotAllowed", "unsupported request")),
+    };
+    if let Some((objects, resource_version)) = saved {
+        state.objects = objects;
+        state.resource_version = resource_version;
+    }
+
+    match result {
+        Ok(value) => (StatusCode::OK, value),
+        Err(error) => error,
+    }
+}
+
+type Handled = Result<Value, (StatusCode, Value)>;
+
+fn not_found(name: &str) -> (StatusCode, Value) {
+    status(404, "NotFound", &format!("\"{}\" not found", name))
+}
+
+fn get(state: &State, collection: &str, name: &str) -> Handled {
+    state
+        .objects
+        .get(&(collection.to_string(), name.to_string()))
+        .cloned()
+        .ok_or_else(|| not_found(name))
+}
+
+/// Check whether an object matches a label selector of `key`, `!key`,
+/// `key=value` and `key!=value` terms
+fn matches_labels(value: &Value, selector: &str) -> bool {
+    let labels = &value["metadata"]["labels"];
+    selector.split(',').filter(|t| !t.is_empty()).all(|term| {
+        if let Some(key) = term.strip_prefix('!') {
+            labels.get(key).is_none()
+        } else if let Some((key, expected)) = term.split_once("!=") {
+            labels[key].as_str() != Some(expected)
+        } else if let Some((key, expected)) = term.split_once('=') {
+            labels[key].as_str() == Some(expected.trim_start_matches('='))
+        } else {
+            labels.get(term).is_some()
+        }
+    })
+}
+
+fn list(state: &State, collection: &str, resource: &str, query: &str) -> Value {
+    let selector = query
+        .split('&')
+        .find_map(|p| p.strip_prefix("labelSelector="))
+        .map(percent_decode)
+        .unwrap_or_default();
+    let items: Vec<Value> = state
+        .objects
+        .iter()
+        .filter(|((c, _), value)| c == collection && matches_labels(value, &selector))
+        .map(|(_, value)| value.clone())
+        .collect();
+    let kind = RESOURCES.iter().find(|(r, _)| *r == resource).unwrap().1;
+    json!({
+        "apiVersion": "v1",
+        "kind": format!("{}List", kind),
+        "metadata": { "resourceVersion": state.resource_version.to_string() },
+        "items": items,
+    })
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap();
+                out.push(u8::from_str_radix(hex, 16).unwrap());
+                i += 3;
+            }
+            b'+' => {
+                out.push(b' ');
+                i += 1;
+            }
+            b => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(out).unwrap()
+}
+
+fn create(state: &mut State, collection: &str, namespace: Option<String>, body: &[u8]) -> Handled {
+    let mut value: Value =
+        serde_json::from_slice(body).map_err(|e| status(400, "BadRequest", &e.to_string()))?;
+    let name = value["metadata"]["name"]
+        .as_str()
+        .ok_or_else(|| status(422, "Invalid", "metadata.name is required"))?
+        .to_string();
+    let key = (collection.to_string(), name.clone());
+    if state.objects.contains_key(&key) {
+        return Err(status(
+            409,
+            "AlreadyExists",
+            &format!("\"{}\" already exists", name),
+        ));
+    }
+    if let Some(namespace) = namespace {
+        value["metadata"]["namespace"] = json!(namespace);
+    }
+    initialize_metadata(state, &mut value);
+    state.objects.insert(key, value.clone());
+    Ok(value)
+}
+
+/// Check the resourceVersion precondition of a write
+fn check_resource_version(existing: &Value, written: &Value) -> Result<(), (StatusCode, Value)> {
+    match written["metadata"]["resourceVersion"].as_str() {
+        Some(expected) if existing["metadata"]["resourceVersion"].as_str() != Some(expected) => {
+            Err(status(
+                409,
+                "Conflict",
+                "the object has been modified; please apply your changes to the latest version and try again",
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn replace(state: &mut State, collection: &str, name: &str, body: &[u8]) -> Handled {
+    let key = (collection.to_string(), name.to_string());
+    let existing = state
+        .objects
+        .get(&key)
+        .cloned()
+        .ok_or_else(|| not_found(name))?;
+    let mut value: Value =
+        serde_json::from_slice(body).map_err(|e| status(400, "BadRequest", &e.to_string()))?;
+    check_resource_version(&existing, &value)?;
+    for field in ["uid", "creationTimestamp", "namespace"] {
+        value["metadata"][field] = existing["metadata"][field].clone();
+    }
+    value["metadata"]["resourceVersion"] = json!(state.next_resource_version());
+    state.objects.insert(key, value.clone());
+    Ok(value)
+}
+
+fn patch(
+    state: &mut State,
+    collection: &str,
+    name: &str,
+    namespace: Option<String>,
+    content_type: &str,
+    body: &[u8],
+) -> Handled {
+    let bad_request = |e: String| status(400, "BadRequest", &e);
+    let key = (collection.to_string(), name.to_string());
+    let existing = state.objects.get(&key).cloned();
+
+    let mut value = match content_type {
+        "application/apply-patch+yaml" => {
+            let applied: Value =
+                serde_json::from_slice(body).map_err(|e| bad_request(e.to_string()))?;
+            match existing {
+                None => {
+                    let mut value = applied;
+                    value["metadata"]["name"] = json!(name);
+                    if let Some(namespace) = namespace {
+                        value["metadata"]["namespace"] = json!(namespace);
+                    }
+                    initialize_metadata(state, &mut value);
+                    state.objects.insert(key, value.clone());
+                    return Ok(value);
+                }
+                Some(mut value) => {
+                    check_resource_version(&value, &applied)?;
+                    for field in ["data", "binaryData"] {
+                        if applied.get(field).is_some() {
+                            value[field] = Value::Null;
+                        }
+                    }
+                    if applied["spec"].get("taints").is_some() {
+                        value["spec"]["taints"] = Value::Null;
+                    }
+                    json_patch::merge(&mut value, &applied);
+                    value
+                }
+            }
+        }
+        "application/merge-patch+json" | "application/strategic-merge-patch+json" => {
+            let mut value = existing.ok_or_else(|| not_found(name))?;
+            let patch: Value =
+                serde_json::from_slice(body).map_err(|e| bad_request(e.to_string()))?;
+            check_resource_version(&value, &patch)?;
+            json_patch::merge(&mut value, &patch);
+            value
+        }
+        "application/json-patch+json" => {
+            let mut value = existing.ok_or_else(|| not_found(name))?;
+            let patch: json_patch::Patch =
+                serde_json::from_slice(body).map_err(|e| bad_request(e.to_string()))?;
+            // A missing field tests equal to null, like on a real API server
+            if value["metadata"].get("finalizers").is_none() {
+                value["metadata"]["finalizers"] = Value::Null;
+            }
+            json_patch::patch(&mut value, &patch)
+                .map_err(|e| status(422, "Invalid", &e.to_string()))?;
+            value
+        }
+        other => return Err(status(415, "UnsupportedMediaType", other)),
+    };
+
+    // Null fields are absent, and an emptied finalizer list completes deletion
+    let metadata = value["metadata"].as_object_mut().unwrap();
+    metadata.retain(|_, v| !v.is_null());
+    let finalizers_empty = metadata
+        .get("finalizers")
+        .and_then(Value::as_array)
+        .is_none_or(|f| f.is_empty());
+    if finalizers_empty {
+        metadata.remove("finalizers");
+    }
+    let deleting = metadata.contains_key("deletionTimestamp");
+    metadata.insert(
+        "resourceVersion".to_string(),
+        json!(state.next_resource_version()),
+    );
+
+    if deleting && finalizers_empty {
+        state.objects.remove(&key);
+    } else {
+        state.objects.insert(key, value.clone());
+    }
+    Ok(value)
+}
+
+fn delete(state: &mut State, collection: &str, name: &str) -> Handled {
+    let key = (collection.to_string(), name.to_string());
+    let mut value = state
+        .objects
+        .get(&key)
+        .cloned()
+        .ok_or_else(|| not_found(name))?;
+    let has_finalizers = value["metadata"]["finalizers"]
+        .as_array()
+        .is_some_and(|f| !f.is_empty());
+    if !has_finalizers {
+        state.objects.remove(&key);
+        return Ok(value);
+    }
+    if value["metadata"].get("deletionTimestamp").is_none() {
+        value["metadata"]["deletionTimestamp"] =
+            json!(Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true));
+        value["metadata"]["resourceVersion"] = json!(state.next_resource_version());
+        state.objects.insert(key, value.clone());
+    }
+    Ok(value)
+}
@@ -0,0 +1,207 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use super::common::FakeApi;
+    use node_taint_preserver::{
+        config::{watch_config_file, ConfigError},
+        Config, ConfigOverrides, Context, NodeSelectionMode, RestoreTrigger,
+    };
+    use rand::{distr::Alphanumeric, rng, Rng};
+    use std::{path::PathBuf, sync::Arc, time::Duration};
+
+    /// Write a config file with a random name to the temp directory
+    fn write_config(extension: &str, contents: &str) -> PathBuf {
+        let name: String = rng()
+            .sample_iter(&Alphanumeric)
+            .take(10)
+            .map(char::from)
+            .collect();
+        let path = std::env::temp_dir().join(format!("config-{}.{}", name, extension));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    /// Load a config file without any other overrides
+    fn load(path: PathBuf) -> Result<Config, ConfigError> {
+        Config::load(&ConfigOverrides {
+            config_file: Some(path),
+            ..Default::default()
+        })
+    }
+
+    /// Test 1: Defaults are valid
+    #[test]
+    fn test_defaults_valid() {
+        let config = Config::load(&ConfigOverrides::default()).unwrap();
+        assert_eq!(config, Config::default());
+        assert_eq!(config.configmap_namespace, "default");
+        assert!(config.admin_addr.ip().is_loopback());
+    }
+
+    /// Test 2: YAML file
+    #[test]
+    fn test_yaml_file() {
+        let path = write_config(
+            "yaml",
+            r#"
+configmap_namespace: taints
+extra_protected_prefixes: ["myorg.com/", " internal.company.io/ "]
+node_selection_mode: opt-in
+restore_trigger: startup-taint
+enforce_records: true
+"#,
+        );
+        let config = load(path).unwrap();
+        assert_eq!(config.configmap_namespace, "taints");
+        assert_eq!(
+            config.extra_protected_prefixes,
+            vec!["myorg.com/", "internal.company.io/"]
+        );
+        assert_eq!(config.node_selection_mode, NodeSelectionMode::OptIn);
+        assert_eq!(config.restore_trigger, RestoreTrigger::StartupTaint);
+        assert!(config.enforce_records);
+        assert_eq!(config.requeue_seconds, Config::default().requeue_seconds);
+    }
+
+    /// Test 3: TOML file
+    #[test]
+    fn test_toml_file() {
+        let path = write_config(
+            "toml",
+            r#"
+requeue_seconds = 5
+max_retry_seconds = 600
+metrics_addr = "127.0.0.1:9090"
+admin_addr = "127.0.0.1:9091"
+"#,
+        );
+        let config = load(path).unwrap();
+        assert_eq!(config.requeue_seconds, 5);
+        assert_eq!(config.max_retry_seconds, 600);
+        assert_eq!(config.metrics_addr.port(), 9090);
+        assert_eq!(config.admin_addr.port(), 9091);
+    }
+
+    /// Test 4: Overrides take precedence over the file
+    #[test]
+    fn test_overrides_precedence() {
+        let path = write_config("yaml", "configmap_namespace: from-file\n");
+        let config = Config::load(&ConfigOverrides {
+            config_file: Some(path),
+            configmap_namespace: Some("from-flag".to_string()),
+            node_label_selector: Some(" ".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(config.configmap_namespace, "from-flag");
+        assert_eq!(config.node_label_selector, None);
+    }
+
+    /// Test 5: Invalid values are rejected
+    #[test]
+    fn test_invalid_values() {
+        let path = write_config("yaml", "requeue_seconds: 0\n");
+        assert!(matches!(
+            load(path),
+            Err(ConfigError::Invalid {
+                field: "requeue_seconds",
+                ..
+            })
+        ));
+
+        let path = write_config("yaml", "configmap_namespace: Not_A_Namespace\n");
+        assert!(matches!(
+            load(path),
+            Err(ConfigError::Invalid {
+                field: "configmap_namespace",
+                ..
+            })
+        ));
+
+        let path = write_config("yaml", "log_filter: \"info,kube=loud\"\n");
+        assert!(matches!(
+            load(path),
+            Err(ConfigError::Invalid {
+                field: "log_filter",
+                ..
+            })
+        ));
+
+        let path = write_config("yaml", "requeue_seconds: 60\nmax_retry_seconds: 30\n");
+        assert!(matches!(
+            load(path),
+            Err(ConfigError::Invalid {
+                field: "max_retry_seconds",
+                ..
+            })
+        ));
+    }
+
+    /// Test 6: Unknown fields and formats are rejected
+    #[test]
+    fn test_unknown_fields_and_formats() {
+        let path = write_config("yaml", "configmap_namespce: typo\n");
+        assert!(matches!(load(path), Err(ConfigError::Parse { .. })));
+
+        let path = write_config("json", "{}");
+        assert!(matches!(load(path), Err(ConfigError::UnsupportedFormat(_))));
+
+        let path = std::env::temp_dir().join("missing-config.yaml");
+        assert!(matches!(load(path), Err(ConfigError::Read { .. })));
+    }
+
+    /// Test 7: Changes to the config file are applied at runtime, except for
+    /// fields that only apply after a restart, and invalid changes are ignored
+    #[tokio::test(start_paused = true)]
+    async fn test_config_file_reloaded() {
+        let path = write_config(
+            "yaml",
+            "configmap_namespace: taints
+requeue_seconds: 5
+",
+        );
+        let overrides = ConfigOverrides {
+            config_file: Some(path.clone()),
+            ..Default::default()
+        };
+        let api = FakeApi::new();
+        let ctx = Arc::new(Context::new(
+            api.client(),
+            Config::load(&overrides).unwrap(),
+        ));
+        tokio::spawn(watch_config_file(ctx.clone(), overrides));
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        std::fs::write(
+            &path,
+            r#"
+configmap_namespace: moved
+shard_prefix_length: 3
+node_label_selector: pool=gpu
+requeue_seconds: 7
+enforce_records: true
+"#,
+        )
+        .unwrap();
+        tokio::time::sleep(Duration::from_secs(30)).await;
+        let config = ctx.config();
+        assert_eq!(config.requeue_seconds, 7);
+        assert!(config.enforce_records);
+        assert_eq!(config.configmap_namespace, "taints");
+        assert_eq!(
+            config.shard_prefix_length,
+            Config::default().shard_prefix_length
+        );
+        assert_eq!(config.node_label_selector, None);
+
+        std::fs::write(
+            &path,
+            "requeue_seconds: 0
+",
+        )
+        .unwrap();
+        tokio::time::sleep(Duration::from_secs(30)).await;
+        assert_eq!(ctx.config().requeue_seconds, 7);
+    }
+}
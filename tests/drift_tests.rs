@@ -0,0 +1,82 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use super::common::{node, store_node, taint_keys, FakeApi};
+    use http::Method;
+    use k8s_openapi::chrono::{SecondsFormat, TimeDelta, Utc};
+    use kube::ResourceExt;
+    use node_taint_preserver::{drift::detect_drift, Config, Context};
+    use std::sync::Arc;
+
+    const EXPIRY_ANNOTATION: &str = "nodetaintpreserver.example.com/taint-expiry";
+
+    /// Test 1: Drift reports list added, removed and changed custom taints of
+    /// nodes with a record, ignoring protected taints and nodes without one
+    #[tokio::test]
+    async fn test_drift_report() {
+        let api = FakeApi::new();
+        let ctx = Arc::new(Context::new(api.client(), Config::default()));
+        store_node(
+            &api,
+            &ctx,
+            &node(
+                "worker-1",
+                &[
+                    ("dedicated", "gpu", "NoSchedule"),
+                    ("team", "a", "NoExecute"),
+                ],
+            ),
+        )
+        .await;
+        api.add_node(&node(
+            "worker-1",
+            &[
+                ("dedicated", "batch", "NoSchedule"),
+                ("canary", "true", "PreferNoSchedule"),
+                ("node.kubernetes.io/unschedulable", "", "NoSchedule"),
+            ],
+        ));
+        api.add_node(&node("worker-2", &[("canary", "true", "NoSchedule")]));
+
+        let report = detect_drift(&ctx).await.unwrap();
+        assert_eq!(report.len(), 1);
+        let drift = &report[0];
+        assert_eq!(drift.node, "worker-1");
+        assert_eq!(taint_keys(&drift.added), ["canary"]);
+        assert_eq!(taint_keys(&drift.removed), ["team"]);
+        assert_eq!(drift.changed.len(), 1);
+        let (stored, live) = &drift.changed[0];
+        assert_eq!(stored.value.as_deref(), Some("gpu"));
+        assert_eq!(live.value.as_deref(), Some("batch"));
+    }
+
+    /// Test 2: Stored taints that expired are no drift, and failing to list
+    /// records fails the report
+    #[tokio::test]
+    async fn test_drift_expired_and_list_failure() {
+        let api = FakeApi::new();
+        let ctx = Arc::new(Context::new(api.client(), Config::default()));
+        store_node(
+            &api,
+            &ctx,
+            &node("worker-1", &[("maintenance", "true", "NoSchedule")]),
+        )
+        .await;
+        let mut live = node("worker-1", &[]);
+        let expired =
+            (Utc::now() - TimeDelta::seconds(60)).to_rfc3339_opts(SecondsFormat::Secs, true);
+        live.annotations_mut().insert(
+            EXPIRY_ANNOTATION.to_string(),
+            format!("maintenance={}", expired),
+        );
+        api.add_node(&live);
+
+        let report = detect_drift(&ctx).await.unwrap();
+        assert_eq!(report.len(), 1);
+        assert!(report[0].is_empty(), "{:?}", report[0]);
+
+        api.fail(Method::GET, "configmaps", 500);
+        assert!(detect_drift(&ctx).await.is_err());
+    }
+}
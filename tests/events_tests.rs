@@ -0,0 +1,89 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use super::common::{node, FakeApi};
+    use http::Method;
+    use kube::{runtime::events::EventType, Resource, ResourceExt};
+    use node_taint_preserver::{events::EventRecorder, init_metrics, PROMETHEUS_REGISTRY};
+
+    /// Current value of `events_suppressed_total` for a reason
+    fn suppressed(reason: &str) -> f64 {
+        PROMETHEUS_REGISTRY
+            .gather()
+            .iter()
+            .filter(|family| family.get_name() == "events_suppressed_total")
+            .flat_map(|family| family.get_metric())
+            .filter(|metric| {
+                metric
+                    .get_label()
+                    .iter()
+                    .any(|label| label.get_name() == "reason" && label.get_value() == reason)
+            })
+            .map(|metric| metric.get_counter().get_value())
+            .sum()
+    }
+
+    /// Test 1: Each object may emit a burst of 10 Events, after which its
+    /// Events are dropped and counted, while other objects are unaffected
+    #[tokio::test]
+    async fn test_events_rate_limited() {
+        init_metrics();
+        let api = FakeApi::new();
+        let recorder = EventRecorder::new(api.client());
+        let flapping = node("worker-1", &[]).object_ref(&());
+        let other = node("worker-2", &[]).object_ref(&());
+
+        let mut outcomes = Vec::new();
+        for i in 0..12 {
+            let note = format!("flap {}", i);
+            outcomes.push(
+                recorder
+                    .publish(&flapping, EventType::Normal, "RateLimitTest", &note)
+                    .await,
+            );
+        }
+        assert_eq!(outcomes[..10], ["published"; 10]);
+        assert_eq!(outcomes[10..], ["suppressed"; 2]);
+        assert_eq!(suppressed("RateLimitTest"), 2.0);
+        assert!(api.has_event("RateLimitTest"));
+
+        let outcome = recorder
+            .publish(&other, EventType::Normal, "RateLimitTest", "other")
+            .await;
+        assert_eq!(outcome, "published");
+        assert_eq!(suppressed("RateLimitTest"), 2.0);
+    }
+
+    /// Test 2: Events are written with the events.k8s.io API that the RBAC
+    /// rules grant, and refer to the node by its UID
+    #[tokio::test]
+    async fn test_events_refer_to_node_uid() {
+        let api = FakeApi::new();
+        let recorder = EventRecorder::new(api.client());
+        api.add_node(&node("worker-1", &[]));
+        let live = api.node("worker-1").unwrap();
+
+        let outcome = recorder
+            .publish(&live.object_ref(&()), EventType::Normal, "UidTest", "test")
+            .await;
+        assert_eq!(outcome, "published");
+
+        let event = api
+            .events()
+            .into_iter()
+            .find(|e| e.reason.as_deref() == Some("UidTest"))
+            .unwrap();
+        let regarding = event.regarding.unwrap();
+        assert_eq!(regarding.kind.as_deref(), Some("Node"));
+        assert_eq!(regarding.name.as_deref(), Some("worker-1"));
+        assert_eq!(regarding.uid, live.uid());
+        assert!(regarding.uid.is_some());
+        assert!(api
+            .requests()
+            .iter()
+            .any(|(method, path)| *method == Method::POST
+                && path.starts_with("apis/events.k8s.io/v1/")
+                && path.ends_with("/events")));
+    }
+}
@@ -0,0 +1,203 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use super::common::{node, restore_node, store_node, sync_record_cache, FakeApi};
+    use k8s_openapi::{
+        api::core::v1::Node,
+        chrono::{SecondsFormat, TimeDelta, Utc},
+    };
+    use kube::{runtime::controller::Action, ResourceExt};
+    use node_taint_preserver::{reconcile, Config, Context, StorageLayout};
+    use std::{collections::BTreeMap, sync::Arc};
+
+    const EXPIRY_ANNOTATION: &str = "nodetaintpreserver.example.com/taint-expiry";
+    const ENFORCED_ANNOTATION: &str = "nodetaintpreserver.example.com/enforced";
+
+    /// RFC 3339 time the given number of seconds from now
+    fn from_now(seconds: i64) -> String {
+        (Utc::now() + TimeDelta::seconds(seconds)).to_rfc3339_opts(SecondsFormat::Secs, true)
+    }
+
+    fn with_expiry(mut node: Node, expiry: &str) -> Node {
+        node.annotations_mut()
+            .insert(EXPIRY_ANNOTATION.to_string(), expiry.to_string());
+        node
+    }
+
+    async fn reconcile_node(api: &FakeApi, ctx: &Arc<Context>, name: &str) -> Action {
+        let node = api.node(name).expect("node should exist");
+        reconcile(Arc::new(node), ctx.clone()).await.unwrap()
+    }
+
+    fn taint_keys(api: &FakeApi, name: &str) -> Vec<String> {
+        let node = api.node(name).unwrap();
+        node.spec
+            .and_then(|spec| spec.taints)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|t| t.key)
+            .collect()
+    }
+
+    fn has_event(api: &FakeApi, reason: &str) -> bool {
+        api.events()
+            .iter()
+            .any(|e| e.reason.as_deref() == Some(reason))
+    }
+
+    /// Test 1: Expired taints are removed from live nodes, and taints of keys
+    /// with a TTL get an expiry time
+    #[tokio::test]
+    async fn test_expire_live_taints() {
+        let api = FakeApi::new();
+        let config = Config {
+            taint_ttl_seconds: BTreeMap::from([("drain".to_string(), 3600)]),
+            ..Default::default()
+        };
+        let ctx = Arc::new(Context::new(api.client(), config));
+
+        let expiry = format!("maintenance={},window={}", from_now(-60), from_now(3600));
+        api.add_node(&with_expiry(
+            node(
+                "worker-1",
+                &[
+                    ("maintenance", "true", "NoSchedule"),
+                    ("window", "true", "NoSchedule"),
+                    ("drain", "true", "NoSchedule"),
+                    ("node.kubernetes.io/unschedulable", "", "NoSchedule"),
+                ],
+            ),
+            &expiry,
+        ));
+        // The first reconcile only adds the finalizer
+        reconcile_node(&api, &ctx, "worker-1").await;
+        reconcile_node(&api, &ctx, "worker-1").await;
+
+        assert_eq!(
+            taint_keys(&api, "worker-1"),
+            ["window", "drain", "node.kubernetes.io/unschedulable"]
+        );
+        assert!(has_event(&api, "TaintsExpired"));
+        let annotation = api.node("worker-1").unwrap().annotations()[EXPIRY_ANNOTATION].clone();
+        assert!(!annotation.contains("maintenance="), "{}", annotation);
+        assert!(annotation.contains("drain="), "{}", annotation);
+
+        // Nothing left to expire, so the node is checked again when the
+        // next taint expires
+        let action = reconcile_node(&api, &ctx, "worker-1").await;
+        assert_ne!(action, Action::await_change());
+        assert_eq!(taint_keys(&api, "worker-1").len(), 3);
+
+        let config = Config {
+            taint_ttl_seconds: BTreeMap::from([("drain".to_string(), 0)]),
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+    }
+
+    /// Test 2: Taints that expired after they were stored are not restored
+    /// in either layout, and the others keep their expiry time on the new node
+    #[tokio::test]
+    async fn test_expired_taints_not_restored() {
+        for storage_layout in [StorageLayout::PerNode, StorageLayout::Sharded] {
+            let api = FakeApi::new();
+            let config = Config {
+                storage_layout,
+                ..Default::default()
+            };
+            let ctx = Arc::new(Context::new(api.client(), config));
+
+            let window = from_now(3600);
+            let expiry = format!("maintenance={},window={}", from_now(-60), window);
+            api.add_node(&with_expiry(
+                node(
+                    "worker-1",
+                    &[
+                        ("maintenance", "true", "NoSchedule"),
+                        ("window", "true", "NoSchedule"),
+                        ("dedicated", "gpu", "NoSchedule"),
+                    ],
+                ),
+                &expiry,
+            ));
+            // Deleted before its taints were checked for expiry
+            reconcile_node(&api, &ctx, "worker-1").await;
+            api.delete_node("worker-1");
+            reconcile_node(&api, &ctx, "worker-1").await;
+
+            api.add_node(&node("worker-1", &[]));
+            reconcile_node(&api, &ctx, "worker-1").await;
+            reconcile_node(&api, &ctx, "worker-1").await;
+
+            assert_eq!(taint_keys(&api, "worker-1"), ["window", "dedicated"]);
+            assert!(has_event(&api, "ExpiredTaintsSkipped"));
+            assert_eq!(
+                api.node("worker-1").unwrap().annotations()[EXPIRY_ANNOTATION],
+                format!("window={}", window)
+            );
+        }
+    }
+
+    /// Test 3: Expired taints are not re-applied by enforced records, and a
+    /// taint added again with the key gets a new expiry time
+    #[tokio::test]
+    async fn test_expired_key_added_again() {
+        let api = FakeApi::new();
+        let plain = Arc::new(Context::new(api.client(), Config::default()));
+        store_node(
+            &api,
+            &plain,
+            &node("worker-1", &[("maintenance", "true", "NoSchedule")]),
+        )
+        .await;
+        let mut record = api.configmaps("default").remove(0);
+        record
+            .annotations_mut()
+            .insert(ENFORCED_ANNOTATION.to_string(), "true".to_string());
+        api.add_configmap("default", &record);
+
+        let config = Config {
+            enforce_records: true,
+            taint_ttl_seconds: BTreeMap::from([("maintenance".to_string(), 3600)]),
+            ..Default::default()
+        };
+        let ctx = Arc::new(Context::new(api.client(), config));
+        sync_record_cache(&ctx).await;
+        assert_eq!(restore_node(&api, &ctx, "worker-1").await, ["maintenance"]);
+        reconcile_node(&api, &ctx, "worker-1").await;
+        assert!(
+            api.node("worker-1").unwrap().annotations()[EXPIRY_ANNOTATION]
+                .starts_with("maintenance=")
+        );
+
+        // The taint expires, and its entry is dropped with it
+        let expired = with_expiry(
+            api.node("worker-1").unwrap(),
+            &format!("maintenance={}", from_now(-60)),
+        );
+        api.add_node(&expired);
+        reconcile_node(&api, &ctx, "worker-1").await;
+        reconcile_node(&api, &ctx, "worker-1").await;
+        assert!(api.node_taint_keys("worker-1").is_empty());
+        assert!(!api
+            .node("worker-1")
+            .unwrap()
+            .annotations()
+            .contains_key(EXPIRY_ANNOTATION));
+
+        // Adding the taint again starts a new TTL
+        let mut tainted = api.node("worker-1").unwrap();
+        tainted.spec = node("worker-1", &[("maintenance", "true", "NoSchedule")]).spec;
+        api.add_node(&tainted);
+        reconcile_node(&api, &ctx, "worker-1").await;
+        reconcile_node(&api, &ctx, "worker-1").await;
+        assert_eq!(api.node_taint_keys("worker-1"), ["maintenance"]);
+        let annotation = api.node("worker-1").unwrap().annotations()[EXPIRY_ANNOTATION].clone();
+        assert!(
+            annotation > format!("maintenance={}", from_now(3500)),
+            "{}",
+            annotation
+        );
+    }
+}
@@ -0,0 +1,186 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use super::common::{node, node_taints, FakeApi};
+    use k8s_openapi::{
+        api::core::v1::{Node, Taint},
+        chrono::{SecondsFormat, TimeDelta, Utc},
+    };
+    use kube::ResourceExt;
+    use node_taint_preserver::{
+        history::{node_history, restore_snapshot},
+        reconcile,
+        storage::CaptureReason,
+        Config, Context, Error, StorageLayout,
+    };
+    use std::{collections::BTreeMap, sync::Arc};
+
+    const EXPIRY_ANNOTATION: &str = "nodetaintpreserver.example.com/taint-expiry";
+    const ZONE_LABEL: &str = "topology.kubernetes.io/zone";
+
+    /// RFC 3339 time the given number of seconds from now
+    fn from_now(seconds: i64) -> String {
+        (Utc::now() + TimeDelta::seconds(seconds)).to_rfc3339_opts(SecondsFormat::Secs, true)
+    }
+
+    fn taint_keys(taints: &[Taint]) -> Vec<String> {
+        taints.iter().map(|t| t.key.clone()).collect()
+    }
+
+    async fn reconcile_node(api: &FakeApi, ctx: &Arc<Context>, name: &str) {
+        let node = api.node(name).expect("node should exist");
+        reconcile(Arc::new(node), ctx.clone()).await.unwrap();
+    }
+
+    /// Run a node through its first reconcile, deletion and cleanup
+    async fn store_node(api: &FakeApi, ctx: &Arc<Context>, node: &Node) {
+        let name = node.name_any();
+        api.add_node(node);
+        reconcile_node(api, ctx, &name).await;
+        api.delete_node(&name);
+        reconcile_node(api, ctx, &name).await;
+    }
+
+    /// Test 1: Records keep the newest snapshots in both layouts, so the
+    /// taints of an earlier deletion survive later ones
+    #[tokio::test]
+    async fn test_history_kept() {
+        for storage_layout in [StorageLayout::PerNode, StorageLayout::Sharded] {
+            let api = FakeApi::new();
+            let config = Config {
+                storage_layout,
+                history_length: 3,
+                ..Default::default()
+            };
+            let ctx = Arc::new(Context::new(api.client(), config));
+            for key in ["first", "second", "third", "fourth"] {
+                store_node(&api, &ctx, &node("worker-1", &[(key, "a", "NoSchedule")])).await;
+            }
+            // Taints were stripped before the node was deleted again
+            store_node(&api, &ctx, &node("worker-1", &[])).await;
+
+            let history = node_history(&ctx, "worker-1").await.unwrap();
+            let keys: Vec<Vec<String>> = history.iter().map(|s| taint_keys(&s.taints)).collect();
+            assert_eq!(keys, [vec![], vec!["fourth"], vec!["third"]]);
+            assert!(history
+                .iter()
+                .all(|s| s.reason == CaptureReason::NodeDeleted));
+            assert!(history[0].captured_at >= history[1].captured_at);
+        }
+    }
+
+    /// Test 2: A snapshot replaces the custom taints of the live node,
+    /// keeping protected taints
+    #[tokio::test]
+    async fn test_restore_snapshot() {
+        let api = FakeApi::new();
+        let ctx = Arc::new(Context::new(api.client(), Config::default()));
+        store_node(
+            &api,
+            &ctx,
+            &node("worker-1", &[("dedicated", "gpu", "NoSchedule")]),
+        )
+        .await;
+        store_node(&api, &ctx, &node("worker-1", &[("team", "a", "NoExecute")])).await;
+
+        api.add_node(&node(
+            "worker-1",
+            &[
+                ("team", "a", "NoExecute"),
+                ("node.kubernetes.io/unschedulable", "", "NoSchedule"),
+            ],
+        ));
+        let live = |api: &FakeApi| {
+            let node = api.node("worker-1").unwrap();
+            taint_keys(&node.spec.unwrap().taints.unwrap_or_default())
+        };
+
+        let taints = restore_snapshot(&ctx, "worker-1", 1, true).await.unwrap();
+        assert_eq!(
+            taint_keys(&taints),
+            ["node.kubernetes.io/unschedulable", "dedicated"]
+        );
+        assert_eq!(
+            live(&api),
+            ["team", "node.kubernetes.io/unschedulable"],
+            "dry run"
+        );
+
+        restore_snapshot(&ctx, "worker-1", 1, false).await.unwrap();
+        assert_eq!(
+            live(&api),
+            ["node.kubernetes.io/unschedulable", "dedicated"]
+        );
+        assert!(api
+            .events()
+            .iter()
+            .any(|e| e.reason.as_deref() == Some("SnapshotRestored")));
+
+        let error = restore_snapshot(&ctx, "worker-1", 2, false)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, Error::SnapshotNotFound(_, 2)), "{}", error);
+    }
+
+    /// Test 3: Snapshot restores skip expired taints, keep the expiry time
+    /// of the others and render value templates, failing without a change
+    /// when a template fails
+    #[tokio::test]
+    async fn test_restore_snapshot_expiry_and_templates() {
+        let api = FakeApi::new();
+        let config = Config {
+            taint_templates: BTreeMap::from([(
+                "dedicated".to_string(),
+                "{label:topology.kubernetes.io/zone}-batch".to_string(),
+            )]),
+            ..Default::default()
+        };
+        let ctx = Arc::new(Context::new(api.client(), config));
+        let window = from_now(3600);
+        let mut old = node(
+            "worker-1",
+            &[
+                ("maintenance", "true", "NoSchedule"),
+                ("window", "true", "NoSchedule"),
+                ("dedicated", "zone-a-batch", "NoSchedule"),
+            ],
+        );
+        old.annotations_mut().insert(
+            EXPIRY_ANNOTATION.to_string(),
+            format!("maintenance={},window={}", from_now(-60), window),
+        );
+        store_node(&api, &ctx, &old).await;
+
+        // The live node has no zone label to render the template with
+        api.add_node(&node("worker-1", &[("team", "a", "NoExecute")]));
+        let error = restore_snapshot(&ctx, "worker-1", 0, false)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, Error::TemplateFailed(..)), "{}", error);
+        assert_eq!(api.node_taint_keys("worker-1"), ["team"]);
+
+        let mut labeled = api.node("worker-1").unwrap();
+        labeled
+            .labels_mut()
+            .insert(ZONE_LABEL.to_string(), "zone-b".to_string());
+        api.add_node(&labeled);
+        let taints = restore_snapshot(&ctx, "worker-1", 0, false).await.unwrap();
+        assert_eq!(taint_keys(&taints), ["window", "dedicated"]);
+        let restored = api.node("worker-1").unwrap();
+        let dedicated = node_taints(&restored)
+            .into_iter()
+            .find(|t| t.key == "dedicated")
+            .unwrap();
+        assert_eq!(dedicated.value.as_deref(), Some("zone-b-batch"));
+        assert_eq!(
+            restored.annotations()[EXPIRY_ANNOTATION],
+            format!("window={}", window)
+        );
+        assert!(api.events().iter().any(|e| e
+            .note
+            .as_deref()
+            .unwrap_or_default()
+            .contains("maintenance")));
+    }
+}
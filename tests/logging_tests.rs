@@ -0,0 +1,84 @@
+#[cfg(test)]
+mod tests {
+    use node_taint_preserver::{
+        logging::{json_layer, LogControl},
+        telemetry,
+    };
+    use serde_json::Value;
+    use std::{
+        io::Write,
+        sync::{Arc, Mutex},
+    };
+    use tracing::field;
+    use tracing_subscriber::prelude::*;
+
+    /// Log output captured in memory
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Test 1: The log filter can be changed at runtime
+    #[test]
+    fn test_change_log_filter() {
+        let (control, _layer) = LogControl::new("info,kube=warn").unwrap();
+        assert_eq!(control.directives(), "info,kube=warn");
+
+        control
+            .set_directives("warn,node_taint_preserver=debug")
+            .unwrap();
+        assert_eq!(control.directives(), "warn,node_taint_preserver=debug");
+    }
+
+    /// Test 2: Invalid filters are rejected and leave the filter unchanged
+    #[test]
+    fn test_invalid_log_filter() {
+        assert!(LogControl::new("info,kube=loud").is_err());
+
+        let (control, _layer) = LogControl::new("info").unwrap();
+        assert!(control.set_directives("kube=loud").is_err());
+        assert_eq!(control.directives(), "info");
+    }
+
+    /// Test 3: JSON lines logged in spans nested in a reconcile carry the
+    /// trace ID of the reconcile span
+    #[test]
+    fn test_json_lines_carry_trace_id() {
+        let captured = Captured::default();
+        let provider = telemetry::tracer_provider(None).unwrap();
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::registry()
+            .with(json_layer(move || writer.clone()))
+            .with(telemetry::layer(&provider));
+
+        let trace_id = tracing::subscriber::with_default(subscriber, || {
+            let reconcile =
+                tracing::info_span!("reconcile", node = "worker-1", trace_id = field::Empty);
+            let trace_id = telemetry::trace_id(&reconcile).unwrap();
+            reconcile.record("trace_id", &trace_id);
+            let _reconcile = reconcile.enter();
+            let apply = tracing::info_span!("apply_node", node = "worker-1");
+            let _apply = apply.enter();
+            let api_call = tracing::info_span!("api_call", resource = "nodes");
+            let _api_call = api_call.enter();
+            tracing::info!(node = "worker-1", phase = "apply", "Restored taints");
+            trace_id
+        });
+
+        let output = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        let line: Value = serde_json::from_str(output.lines().next().unwrap()).unwrap();
+        assert_eq!(line["phase"], "apply");
+        assert_eq!(line["span"]["name"], "api_call");
+        let spans = line["spans"].as_array().unwrap();
+        assert_eq!(spans[0]["name"], "reconcile");
+        assert_eq!(spans[0]["trace_id"], trace_id);
+    }
+}
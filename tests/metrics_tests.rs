@@ -0,0 +1,65 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use super::common::{node, store_node, FakeApi};
+    use node_taint_preserver::{gather_metrics, Config, Context};
+    use std::sync::Arc;
+
+    /// Label values of the series of a metric
+    fn series(name: &str) -> Vec<Vec<(String, String)>> {
+        gather_metrics()
+            .iter()
+            .filter(|family| family.get_name() == name)
+            .flat_map(|family| family.get_metric())
+            .map(|metric| {
+                metric
+                    .get_label()
+                    .iter()
+                    .map(|label| (label.get_name().to_string(), label.get_value().to_string()))
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn labels(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    /// Test 1: Taint counters are labeled with the node and key by default,
+    /// and only omit the node label when per-node metrics are disabled
+    #[tokio::test]
+    async fn test_per_node_metric_labels() {
+        let api = FakeApi::new();
+        let ctx = Arc::new(Context::new(api.client(), Config::default()));
+        store_node(
+            &api,
+            &ctx,
+            &node("labeled-1", &[("gpu", "a", "NoSchedule")]),
+        )
+        .await;
+        assert!(series("taints_captured_total")
+            .contains(&labels(&[("key", "gpu"), ("node", "labeled-1")])));
+
+        let config = Config {
+            per_node_metrics: false,
+            ..Default::default()
+        };
+        let ctx = Arc::new(Context::new(api.client(), config));
+        store_node(
+            &api,
+            &ctx,
+            &node("unlabeled-1", &[("fpga", "a", "NoSchedule")]),
+        )
+        .await;
+        let captured = series("taints_captured_total");
+        assert!(captured.contains(&labels(&[("key", "fpga")])));
+        assert!(!captured
+            .iter()
+            .flatten()
+            .any(|(_, value)| value == "unlabeled-1" || value.is_empty()));
+    }
+}
@@ -0,0 +1,559 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use super::common::{node, restore_node, sync_record_cache, FakeApi};
+    use http::Method;
+    use k8s_openapi::{
+        api::core::v1::{ConfigMap, Node, NodeCondition, NodeStatus, Taint},
+        apimachinery::pkg::apis::meta::v1::Time,
+        chrono::{TimeDelta, Utc},
+    };
+    use kube::{runtime::controller::Action, ResourceExt};
+    use node_taint_preserver::{
+        adopt_records,
+        breaker::{resume, sync_breaker_state},
+        error_policy,
+        history::node_history,
+        reconcile,
+        storage::CaptureReason,
+        Config, Context, Error, RestoreTrigger, StorageLayout,
+    };
+    use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+    const FINALIZER: &str = "nodetaintpreserver.example.com/finalizer";
+    const RESTORED_ANNOTATION: &str = "nodetaintpreserver.example.com/taints-restored";
+    const ENFORCED_ANNOTATION: &str = "nodetaintpreserver.example.com/enforced";
+    const GATE_KEY: &str = "nodetaintpreserver.example.com/restore-pending";
+
+    fn setup() -> (FakeApi, Arc<Context>) {
+        let api = FakeApi::new();
+        let ctx = Arc::new(Context::new(api.client(), Config::default()));
+        (api, ctx)
+    }
+
+    /// Reconcile the current state of a node
+    async fn reconcile_node(
+        api: &FakeApi,
+        ctx: &Arc<Context>,
+        name: &str,
+    ) -> Result<Action, Error> {
+        let node = api.node(name).expect("node should exist");
+        reconcile(Arc::new(node), ctx.clone()).await
+    }
+
+    /// GET requests for single records
+    fn record_gets(api: &FakeApi) -> usize {
+        api.requests()
+            .iter()
+            .filter(|(method, path)| {
+                method == Method::GET && path.contains("/configmaps/node-taints-")
+            })
+            .count()
+    }
+
+    fn taints(node: &Node) -> Vec<Taint> {
+        node.spec
+            .as_ref()
+            .and_then(|spec| spec.taints.clone())
+            .unwrap_or_default()
+    }
+
+    fn taint_keys(node: &Node) -> Vec<String> {
+        taints(node).into_iter().map(|t| t.key).collect()
+    }
+
+    /// Run a node through its first reconcile, deletion and cleanup, leaving
+    /// its record behind
+    async fn store_node(api: &FakeApi, ctx: &Arc<Context>, node: &Node) {
+        let name = node.name_any();
+        api.add_node(node);
+        reconcile_node(api, ctx, &name).await.unwrap();
+        api.delete_node(&name);
+        reconcile_node(api, ctx, &name).await.unwrap();
+        assert!(api.node(&name).is_none(), "node should be released");
+    }
+
+    /// Test 1: New nodes get our finalizer
+    #[tokio::test]
+    async fn test_finalizer_added() {
+        let (api, ctx) = setup();
+        api.add_node(&node("worker-1", &[]));
+
+        let action = reconcile_node(&api, &ctx, "worker-1").await.unwrap();
+        assert_eq!(action, Action::await_change());
+        assert_eq!(api.node("worker-1").unwrap().finalizers(), [FINALIZER]);
+    }
+
+    /// Test 2: Deleted nodes have their custom taints stored and are released
+    #[tokio::test]
+    async fn test_cleanup_stores_custom_taints() {
+        let (api, ctx) = setup();
+        store_node(
+            &api,
+            &ctx,
+            &node(
+                "worker-1",
+                &[
+                    ("dedicated", "gpu", "NoSchedule"),
+                    ("node.kubernetes.io/unschedulable", "", "NoSchedule"),
+                ],
+            ),
+        )
+        .await;
+
+        let records = api.configmaps("default");
+        assert_eq!(records.len(), 1);
+        let record = &records[0];
+        assert_eq!(
+            record
+                .annotations()
+                .get("nodetaintpreserver.example.com/node-name"),
+            Some(&"worker-1".to_string())
+        );
+        let labels = record.labels();
+        assert_eq!(
+            labels["app.kubernetes.io/managed-by"],
+            "node-taint-preserver"
+        );
+        assert_eq!(labels["app.kubernetes.io/component"], "taint-record");
+        assert!(labels.contains_key("nodetaintpreserver.example.com/node-hash"));
+        let stored: Vec<Taint> = serde_json::from_str(
+            record
+                .data
+                .as_ref()
+                .unwrap()
+                .get("preserved_taints_json")
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].key, "dedicated");
+    }
+
+    /// Test 3: Recreated nodes get their stored taints back without
+    /// overwriting taints they already carry
+    #[tokio::test]
+    async fn test_apply_restores_taints() {
+        let (api, ctx) = setup();
+        store_node(
+            &api,
+            &ctx,
+            &node(
+                "worker-1",
+                &[
+                    ("dedicated", "gpu", "NoSchedule"),
+                    ("team", "a", "NoExecute"),
+                ],
+            ),
+        )
+        .await;
+
+        api.add_node(&node("worker-1", &[("team", "b", "NoExecute")]));
+        reconcile_node(&api, &ctx, "worker-1").await.unwrap();
+        let action = reconcile_node(&api, &ctx, "worker-1").await.unwrap();
+        assert_eq!(action, Action::await_change());
+
+        let restored = api.node("worker-1").unwrap();
+        assert_eq!(taint_keys(&restored), ["team", "dedicated"]);
+        let team = taints(&restored)
+            .into_iter()
+            .find(|t| t.key == "team")
+            .unwrap();
+        assert_eq!(team.value.as_deref(), Some("b"));
+        assert!(restored.annotations().contains_key(RESTORED_ANNOTATION));
+
+        // Reconciling again is a no-op
+        let resource_version = restored.resource_version();
+        reconcile_node(&api, &ctx, "worker-1").await.unwrap();
+        assert_eq!(
+            api.node("worker-1").unwrap().resource_version(),
+            resource_version
+        );
+    }
+
+    /// Test 4: Restores are published as Events referencing the node by UID
+    #[tokio::test]
+    async fn test_restore_event() {
+        let (api, ctx) = setup();
+        store_node(
+            &api,
+            &ctx,
+            &node("worker-1", &[("dedicated", "gpu", "NoSchedule")]),
+        )
+        .await;
+
+        api.add_node(&node("worker-1", &[]));
+        reconcile_node(&api, &ctx, "worker-1").await.unwrap();
+        reconcile_node(&api, &ctx, "worker-1").await.unwrap();
+
+        let uid = api.node("worker-1").unwrap().uid();
+        let event = api
+            .events()
+            .into_iter()
+            .find(|e| e.reason.as_deref() == Some("TaintsRestored"))
+            .expect("TaintsRestored event");
+        let regarding = event.regarding.unwrap();
+        assert_eq!(regarding.kind.as_deref(), Some("Node"));
+        assert_eq!(regarding.uid, uid);
+        assert!(event.note.unwrap().contains("dedicated"));
+    }
+
+    /// Test 5: Opted-out nodes are released without a record
+    #[tokio::test]
+    async fn test_unmanaged_node_released() {
+        let (api, ctx) = setup();
+        api.add_node(&node("worker-1", &[("dedicated", "gpu", "NoSchedule")]));
+        reconcile_node(&api, &ctx, "worker-1").await.unwrap();
+
+        let mut opted_out = api.node("worker-1").unwrap();
+        opted_out.metadata.labels = Some(BTreeMap::from([(
+            "nodetaintpreserver.example.com/skip".to_string(),
+            "true".to_string(),
+        )]));
+        api.add_node(&opted_out);
+        reconcile_node(&api, &ctx, "worker-1").await.unwrap();
+        assert!(api.node("worker-1").unwrap().finalizers().is_empty());
+
+        api.delete_node("worker-1");
+        assert!(api.node("worker-1").is_none());
+        assert!(api.configmaps("default").is_empty());
+    }
+
+    /// Test 6: A failed record write fails the cleanup and keeps the finalizer
+    #[tokio::test]
+    async fn test_cleanup_failure_keeps_finalizer() {
+        let (api, ctx) = setup();
+        api.add_node(&node("worker-1", &[("dedicated", "gpu", "NoSchedule")]));
+        reconcile_node(&api, &ctx, "worker-1").await.unwrap();
+        api.delete_node("worker-1");
+
+        api.fail(Method::PATCH, "configmaps", 500);
+        assert!(reconcile_node(&api, &ctx, "worker-1").await.is_err());
+        assert_eq!(api.node("worker-1").unwrap().finalizers(), [FINALIZER]);
+
+        // The retry succeeds
+        reconcile_node(&api, &ctx, "worker-1").await.unwrap();
+        assert!(api.node("worker-1").is_none());
+        assert_eq!(api.configmaps("default").len(), 1);
+    }
+
+    /// Test 7: Failed reconciles are retried with exponential backoff
+    #[tokio::test]
+    async fn test_error_policy_backoff() {
+        let (_api, ctx) = setup();
+        let node = Arc::new(node("worker-1", &[]));
+        let error = Error::Finalizer("boom".to_string());
+
+        let delays: Vec<Action> = (0..3)
+            .map(|_| error_policy(node.clone(), &error, ctx.clone()))
+            .collect();
+        assert_eq!(
+            delays,
+            [4, 8, 16].map(|secs| Action::requeue(Duration::from_secs(secs)))
+        );
+
+        for _ in 0..20 {
+            error_policy(node.clone(), &error, ctx.clone());
+        }
+        assert_eq!(
+            error_policy(node, &error, ctx),
+            Action::requeue(Duration::from_secs(Config::default().max_retry_seconds))
+        );
+    }
+
+    /// Test 8: Records are read from the synced record cache without a GET
+    #[tokio::test]
+    async fn test_restore_from_record_cache() {
+        let (api, ctx) = setup();
+        store_node(
+            &api,
+            &ctx,
+            &node("worker-1", &[("dedicated", "gpu", "NoSchedule")]),
+        )
+        .await;
+
+        // A restarted controller lists the labeled records into its cache
+        let ctx = Arc::new(Context::new(api.client(), Config::default()));
+        sync_record_cache(&ctx).await;
+        let gets = record_gets(&api);
+
+        api.add_node(&node("worker-1", &[]));
+        reconcile_node(&api, &ctx, "worker-1").await.unwrap();
+        reconcile_node(&api, &ctx, "worker-1").await.unwrap();
+        assert_eq!(taint_keys(&api.node("worker-1").unwrap()), ["dedicated"]);
+        assert_eq!(record_gets(&api), gets);
+    }
+
+    /// Test 9: Records written since the cache last saw them are fetched with
+    /// a live GET
+    #[tokio::test]
+    async fn test_record_cache_miss_falls_back_to_get() {
+        let (api, ctx) = setup();
+        sync_record_cache(&ctx).await;
+
+        // The record is written after the cache synced, and the fake API
+        // never sends the watch event adding it
+        store_node(
+            &api,
+            &ctx,
+            &node("worker-1", &[("dedicated", "gpu", "NoSchedule")]),
+        )
+        .await;
+        let gets = record_gets(&api);
+
+        api.add_node(&node("worker-1", &[]));
+        reconcile_node(&api, &ctx, "worker-1").await.unwrap();
+        reconcile_node(&api, &ctx, "worker-1").await.unwrap();
+        assert_eq!(taint_keys(&api.node("worker-1").unwrap()), ["dedicated"]);
+        assert!(record_gets(&api) > gets);
+    }
+
+    /// Test 10: Records written without labels are adopted
+    #[tokio::test]
+    async fn test_unlabeled_records_adopted() {
+        let (api, ctx) = setup();
+        store_node(
+            &api,
+            &ctx,
+            &node("worker-1", &[("dedicated", "gpu", "NoSchedule")]),
+        )
+        .await;
+        let mut legacy = api.configmaps("default").remove(0);
+        let labels = legacy.metadata.labels.take().unwrap();
+        api.add_configmap("default", &legacy);
+        let mut unrelated = ConfigMap::default();
+        unrelated.metadata.name = Some("unrelated".to_string());
+        api.add_configmap("default", &unrelated);
+
+        assert_eq!(adopt_records(&ctx).await.unwrap(), ["worker-1"]);
+        let adopted = api.configmap("default", &legacy.name_any()).unwrap();
+        assert_eq!(adopted.labels(), &labels);
+        assert!(api
+            .configmap("default", "unrelated")
+            .unwrap()
+            .labels()
+            .is_empty());
+
+        // Adoption is idempotent
+        assert!(adopt_records(&ctx).await.unwrap().is_empty());
+    }
+
+    /// Test 11: A cleanup that timed out stores the taints last seen
+    /// applied, not the ones left on the node when it was deleted
+    #[tokio::test]
+    async fn test_cleanup_timeout_stores_applied_taints() {
+        let (api, ctx) = setup();
+        api.add_node(&node("worker-1", &[("dedicated", "gpu", "NoSchedule")]));
+        reconcile_node(&api, &ctx, "worker-1").await.unwrap();
+        reconcile_node(&api, &ctx, "worker-1").await.unwrap();
+
+        // The taints are stripped right before the node is deleted, and the
+        // record cannot be written until the finalizer timeout passed
+        api.set_node_taints("worker-1", &[]);
+        api.delete_node("worker-1");
+        api.fail(Method::PATCH, "configmaps", 500);
+        assert!(reconcile_node(&api, &ctx, "worker-1").await.is_err());
+        let mut deleting = api.node("worker-1").unwrap();
+        deleting.metadata.deletion_timestamp = Some(Time(Utc::now() - TimeDelta::hours(2)));
+        api.add_node(&deleting);
+        reconcile_node(&api, &ctx, "worker-1").await.unwrap();
+
+        assert!(api.node("worker-1").is_none());
+        let history = node_history(&ctx, "worker-1").await.unwrap();
+        assert_eq!(history[0].reason, CaptureReason::CleanupTimedOut);
+        let stored: Vec<&str> = history[0].taints.iter().map(|t| t.key.as_str()).collect();
+        assert_eq!(stored, ["dedicated"]);
+    }
+
+    /// Test 12: Taints of enforced records are re-applied whenever they go
+    /// missing, on node events and periodic resyncs, and kept on deletion
+    #[tokio::test]
+    async fn test_enforced_record_reapplied() {
+        let (api, ctx) = setup();
+        store_node(
+            &api,
+            &ctx,
+            &node("worker-1", &[("dedicated", "payments", "NoSchedule")]),
+        )
+        .await;
+        let mut record = api.configmaps("default").remove(0);
+        record
+            .annotations_mut()
+            .insert(ENFORCED_ANNOTATION.to_string(), "true".to_string());
+        api.add_configmap("default", &record);
+
+        // Without enforcement, the record is only restored once
+        let ctx = Arc::new(Context::new(api.client(), Config::default()));
+        sync_record_cache(&ctx).await;
+        assert_eq!(restore_node(&api, &ctx, "worker-1").await, ["dedicated"]);
+        api.set_node_taints("worker-1", &[]);
+        assert_eq!(
+            reconcile_node(&api, &ctx, "worker-1").await.unwrap(),
+            Action::await_change()
+        );
+        assert!(api.node_taint_keys("worker-1").is_empty());
+
+        let config = Config {
+            enforce_records: true,
+            ..Default::default()
+        };
+        let resync = Action::requeue(config.enforcement_resync());
+        let ctx = Arc::new(Context::new(api.client(), config));
+        sync_record_cache(&ctx).await;
+        assert_eq!(
+            reconcile_node(&api, &ctx, "worker-1").await.unwrap(),
+            resync
+        );
+        assert_eq!(api.node_taint_keys("worker-1"), ["dedicated"]);
+        assert!(api.has_event("TaintDriftCorrected"));
+
+        // The taint is kept in the record when the node is deleted without it
+        api.set_node_taints(
+            "worker-1",
+            &[("team", "a", "NoExecute")].map(|(key, value, effect)| Taint {
+                key: key.to_string(),
+                value: Some(value.to_string()),
+                effect: effect.to_string(),
+                time_added: None,
+            }),
+        );
+        api.delete_node("worker-1");
+        reconcile_node(&api, &ctx, "worker-1").await.unwrap();
+        assert_eq!(
+            restore_node(&api, &ctx, "worker-1").await,
+            ["team", "dedicated"]
+        );
+    }
+
+    /// Test 13: Nodes registered with the restore gate keep it until their
+    /// taints were restored, and lose it in the same patch
+    #[tokio::test]
+    async fn test_startup_gate_held_and_released() {
+        let api = FakeApi::new();
+        let config = Config {
+            restore_trigger: RestoreTrigger::StartupTaint,
+            circuit_breaker_max_cleanups: 1,
+            ..Default::default()
+        };
+        let ctx = Arc::new(Context::new(api.client(), config));
+
+        // Nodes without a record are released right away
+        api.add_node(&node("worker-3", &[(GATE_KEY, "", "NoSchedule")]));
+        reconcile_node(&api, &ctx, "worker-3").await.unwrap();
+        reconcile_node(&api, &ctx, "worker-3").await.unwrap();
+        assert!(api.node_taint_keys("worker-3").is_empty());
+
+        for name in ["worker-1", "worker-2"] {
+            store_node(
+                &api,
+                &ctx,
+                &node(name, &[("dedicated", "gpu", "NoSchedule")]),
+            )
+            .await;
+        }
+
+        // The open circuit breaker holds restores, and with them the gate
+        api.add_node(&node("worker-1", &[(GATE_KEY, "", "NoSchedule")]));
+        reconcile_node(&api, &ctx, "worker-1").await.unwrap();
+        reconcile_node(&api, &ctx, "worker-1").await.unwrap();
+        assert_eq!(api.node_taint_keys("worker-1"), [GATE_KEY]);
+
+        assert!(resume(&ctx).await.unwrap());
+        sync_breaker_state(&ctx).await.unwrap();
+        reconcile_node(&api, &ctx, "worker-1").await.unwrap();
+        assert_eq!(api.node_taint_keys("worker-1"), ["dedicated"]);
+        assert!(api.has_event("TaintsRestored"));
+
+        // A gate added back later is released again, without a second copy
+        // of the restored taints
+        let mut gated = taints(&api.node("worker-1").unwrap());
+        gated.extend(taints(&node("worker-1", &[(GATE_KEY, "", "NoSchedule")])));
+        api.set_node_taints("worker-1", &gated);
+        reconcile_node(&api, &ctx, "worker-1").await.unwrap();
+        assert_eq!(api.node_taint_keys("worker-1"), ["dedicated"]);
+    }
+
+    /// Test 14: With the on-ready trigger, restores wait for the node to
+    /// report Ready
+    #[tokio::test]
+    async fn test_restore_on_ready() {
+        let api = FakeApi::new();
+        let config = Config {
+            restore_trigger: RestoreTrigger::OnReady,
+            ..Default::default()
+        };
+        let ctx = Arc::new(Context::new(api.client(), config));
+        store_node(
+            &api,
+            &ctx,
+            &node("worker-1", &[("dedicated", "gpu", "NoSchedule")]),
+        )
+        .await;
+
+        assert!(restore_node(&api, &ctx, "worker-1").await.is_empty());
+        let mut ready = api.node("worker-1").unwrap();
+        ready.status = Some(NodeStatus {
+            conditions: Some(vec![NodeCondition {
+                type_: "Ready".to_string(),
+                status: "True".to_string(),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        });
+        api.add_node(&ready);
+        reconcile_node(&api, &ctx, "worker-1").await.unwrap();
+        assert_eq!(api.node_taint_keys("worker-1"), ["dedicated"]);
+    }
+
+    /// Test 15: Nodes without a record are answered by the synced record
+    /// cache, and restores wait for the cache to sync instead of falling
+    /// back to GETs
+    #[tokio::test]
+    async fn test_record_cache_authoritative() {
+        let api = FakeApi::new();
+        for storage_layout in [StorageLayout::PerNode, StorageLayout::Sharded] {
+            let config = Config {
+                storage_layout,
+                ..Default::default()
+            };
+            let ctx = Arc::new(Context::new(api.client(), config.clone()));
+            sync_record_cache(&ctx).await;
+            assert!(restore_node(&api, &ctx, "worker-1").await.is_empty());
+            assert_eq!(record_gets(&api), 0);
+
+            // The watch of a restarted controller has not listed records yet
+            let ctx = Arc::new(Context::new(api.client(), config));
+            let _watch = ctx.watch_records();
+            api.add_node(&node("worker-2", &[]));
+            reconcile_node(&api, &ctx, "worker-2").await.unwrap();
+            assert_eq!(
+                reconcile_node(&api, &ctx, "worker-2").await.unwrap(),
+                Action::requeue(Duration::from_secs(2))
+            );
+            assert_eq!(record_gets(&api), 0);
+        }
+    }
+
+    /// Test 16: Opted-out nodes whose metadata changed concurrently are
+    /// re-read and released
+    #[tokio::test]
+    async fn test_unmanaged_node_release_conflict_retried() {
+        let (api, ctx) = setup();
+        api.add_node(&node("worker-1", &[]));
+        reconcile_node(&api, &ctx, "worker-1").await.unwrap();
+
+        let mut opted_out = api.node("worker-1").unwrap();
+        opted_out.metadata.labels = Some(BTreeMap::from([(
+            "nodetaintpreserver.example.com/skip".to_string(),
+            "true".to_string(),
+        )]));
+        api.add_node(&opted_out);
+        api.fail_object(Method::PATCH, "nodes", "worker-1", 409);
+        reconcile_node(&api, &ctx, "worker-1").await.unwrap();
+        assert!(api.node("worker-1").unwrap().finalizers().is_empty());
+        assert!(api
+            .requests()
+            .contains(&(Method::GET, "api/v1/nodes/worker-1".to_string())));
+    }
+}
@@ -0,0 +1,71 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use super::common::{node, FakeApi};
+    use futures::StreamExt;
+    use k8s_openapi::api::core::v1::Node;
+    use kube::{
+        runtime::{watcher, WatchStreamExt},
+        Api, ResourceExt,
+    };
+    use node_taint_preserver::{drift::detect_drift, uninstall::uninstall, Config, Context};
+    use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+    const FINALIZER: &str = "nodetaintpreserver.example.com/finalizer";
+
+    /// A node in a pool, carrying our finalizer
+    fn pool_node(name: &str, pool: &str) -> Node {
+        let mut node = node(name, &[]);
+        node.metadata.labels = Some(BTreeMap::from([("pool".to_string(), pool.to_string())]));
+        node.metadata.finalizers = Some(vec![FINALIZER.to_string()]);
+        node
+    }
+
+    /// Test 1: The configured selectors restrict the node watch as well as
+    /// the node lists of drift reports and uninstalls
+    #[tokio::test]
+    async fn test_selectors_reach_node_lists() {
+        let api = FakeApi::new();
+        api.add_node(&pool_node("gpu-1", "gpu"));
+        api.add_node(&pool_node("batch-1", "batch"));
+        let config = Config {
+            node_label_selector: Some("pool=gpu".to_string()),
+            node_field_selector: Some("metadata.name!=control-plane".to_string()),
+            ..Default::default()
+        };
+        let ctx = Arc::new(Context::new(api.client(), config));
+        let scope = (
+            "pool=gpu".to_string(),
+            "metadata.name!=control-plane".to_string(),
+        );
+
+        let node_api: Api<Node> = Api::all(api.client());
+        let watched: Vec<Node> = watcher(node_api, ctx.node_watcher_config())
+            .default_backoff()
+            .applied_objects()
+            .take(1)
+            .map(|node| node.unwrap())
+            .collect()
+            .await;
+        assert_eq!(watched[0].name_any(), "gpu-1");
+        assert_eq!(api.selectors("api/v1/nodes"), vec![scope.clone()]);
+
+        detect_drift(&ctx).await.unwrap();
+        assert_eq!(
+            api.selectors("api/v1/nodes"),
+            [scope.clone(), scope.clone()]
+        );
+
+        let report = tokio::time::timeout(Duration::from_secs(5), uninstall(&ctx, false, false))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            api.selectors("api/v1/nodes"),
+            [scope.clone(), scope.clone(), scope]
+        );
+        assert_eq!(report.nodes_released, ["gpu-1"]);
+        assert_eq!(api.node("batch-1").unwrap().finalizers(), [FINALIZER]);
+    }
+}
@@ -0,0 +1,80 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use super::common::{node, reconcile_node, try_reconcile_node, FakeApi};
+    use http::Method;
+    use kube::ResourceExt;
+    use node_taint_preserver::{drain_on_shutdown, Config, Context};
+    use std::{
+        io::Write,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    const FINALIZER: &str = "nodetaintpreserver.example.com/finalizer";
+
+    /// Log output captured in memory
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn setup() -> (FakeApi, Arc<Context>) {
+        let api = FakeApi::new();
+        let ctx = Arc::new(Context::new(api.client(), Config::default()));
+        api.add_node(&node("worker-1", &[]));
+        (api, ctx)
+    }
+
+    /// Test 1: A controller that stops within the grace period interrupts
+    /// nothing
+    #[tokio::test]
+    async fn test_drained_within_grace_period() {
+        let (api, ctx) = setup();
+        let controller = async {
+            reconcile_node(&api, &ctx, "worker-1").await;
+        };
+
+        let interrupted =
+            drain_on_shutdown(&ctx, controller, async {}, Duration::from_secs(5)).await;
+        assert!(interrupted.is_empty());
+        assert_eq!(api.node("worker-1").unwrap().finalizers(), [FINALIZER]);
+        assert!(ctx.in_flight().is_empty());
+    }
+
+    /// Test 2: Reconciles still running when the grace period expires are
+    /// interrupted and reported
+    #[tokio::test]
+    async fn test_grace_period_expired() {
+        let (api, ctx) = setup();
+        api.stall(Method::PATCH, "nodes");
+        let controller = async {
+            try_reconcile_node(&api, &ctx, "worker-1").await.ok();
+        };
+
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+        let interrupted =
+            drain_on_shutdown(&ctx, controller, async {}, Duration::from_millis(50)).await;
+
+        assert_eq!(interrupted, [("worker-1".to_string(), "reconcile")]);
+        assert!(api.node("worker-1").unwrap().finalizers().is_empty());
+        let logs = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        assert!(logs.contains("interrupting reconcile"), "{}", logs);
+        assert!(logs.contains("node=worker-1"), "{}", logs);
+    }
+}
@@ -0,0 +1,513 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use super::common::{node, FakeApi};
+    use http::Method;
+    use k8s_openapi::api::core::v1::{ConfigMap, Node};
+    use kube::ResourceExt;
+    use node_taint_preserver::{
+        crypto::Keyring,
+        reconcile,
+        storage::{migrate_records, reseal_records},
+        Config, Context, Error, StorageLayout,
+    };
+    use sha2::{Digest, Sha256};
+    use std::{path::PathBuf, sync::Arc};
+
+    const SHARD_LABEL: &str = "nodetaintpreserver.example.com/shard";
+
+    fn sharded_config() -> Config {
+        Config {
+            storage_layout: StorageLayout::Sharded,
+            shard_prefix_length: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Hash prefix selecting the shard of a node
+    fn shard_prefix(node_name: &str) -> String {
+        hex::encode(Sha256::digest(node_name.as_bytes()))[..1].to_string()
+    }
+
+    /// Two node names whose records share a shard
+    fn nodes_in_same_shard() -> (String, String) {
+        let first = "worker-0".to_string();
+        let second = (1..)
+            .map(|i| format!("worker-{}", i))
+            .find(|name| shard_prefix(name) == shard_prefix(&first))
+            .unwrap();
+        (first, second)
+    }
+
+    fn shards(api: &FakeApi) -> Vec<ConfigMap> {
+        api.configmaps("default")
+            .into_iter()
+            .filter(|cm| cm.labels().contains_key(SHARD_LABEL))
+            .collect()
+    }
+
+    async fn reconcile_node(api: &FakeApi, ctx: &Arc<Context>, name: &str) {
+        let node = api.node(name).expect("node should exist");
+        reconcile(Arc::new(node), ctx.clone()).await.unwrap();
+    }
+
+    /// Run a node through its first reconcile, deletion and cleanup
+    async fn store_node(api: &FakeApi, ctx: &Arc<Context>, node: &Node) {
+        let name = node.name_any();
+        api.add_node(node);
+        reconcile_node(api, ctx, &name).await;
+        api.delete_node(&name);
+        reconcile_node(api, ctx, &name).await;
+        assert!(api.node(&name).is_none(), "node should be released");
+    }
+
+    /// Recreate a node and return its taint keys once restored
+    async fn restore_node(api: &FakeApi, ctx: &Arc<Context>, name: &str) -> Vec<String> {
+        api.add_node(&node(name, &[]));
+        reconcile_node(api, ctx, name).await;
+        reconcile_node(api, ctx, name).await;
+        let node = api.node(name).unwrap();
+        node.spec
+            .unwrap()
+            .taints
+            .unwrap_or_default()
+            .into_iter()
+            .map(|t| t.key)
+            .collect()
+    }
+
+    /// Test 1: Sharded records of several nodes share a ConfigMap and are restored
+    #[tokio::test]
+    async fn test_sharded_records_restored() {
+        let api = FakeApi::new();
+        let ctx = Arc::new(Context::new(api.client(), sharded_config()));
+        let (first, second) = nodes_in_same_shard();
+        store_node(
+            &api,
+            &ctx,
+            &node(&first, &[("dedicated", "gpu", "NoSchedule")]),
+        )
+        .await;
+        store_node(&api, &ctx, &node(&second, &[("team", "a", "NoExecute")])).await;
+
+        let shards = shards(&api);
+        assert_eq!(shards.len(), 1);
+        assert_eq!(shards[0].labels()[SHARD_LABEL], shard_prefix(&first));
+        assert_eq!(shards[0].data.as_ref().unwrap().len(), 2);
+        assert_eq!(api.configmaps("default").len(), 1, "no per-node records");
+
+        assert_eq!(restore_node(&api, &ctx, &first).await, ["dedicated"]);
+        assert_eq!(restore_node(&api, &ctx, &second).await, ["team"]);
+    }
+
+    /// Test 2: Shard writes losing a resourceVersion race are retried
+    /// without losing the concurrent write
+    #[tokio::test]
+    async fn test_shard_write_conflict_retried() {
+        let api = FakeApi::new();
+        let ctx = Arc::new(Context::new(api.client(), sharded_config()));
+        let (first, second) = nodes_in_same_shard();
+        store_node(
+            &api,
+            &ctx,
+            &node(&first, &[("dedicated", "gpu", "NoSchedule")]),
+        )
+        .await;
+
+        api.fail(Method::PUT, "configmaps", 409);
+        store_node(&api, &ctx, &node(&second, &[("team", "a", "NoExecute")])).await;
+        let puts = api
+            .requests()
+            .iter()
+            .filter(|(method, _)| method == Method::PUT)
+            .count();
+        assert_eq!(puts, 2);
+        assert_eq!(shards(&api)[0].data.as_ref().unwrap().len(), 2);
+
+        // Concurrent cleanups of nodes in the same shard both land
+        let others: Vec<String> = (1..)
+            .map(|i| format!("pool-{}", i))
+            .filter(|name| shard_prefix(name) == shard_prefix(&first))
+            .take(2)
+            .collect();
+        let (third, fourth) = (&others[0], &others[1]);
+        for name in [third, fourth] {
+            api.add_node(&node(name, &[("dedicated", "batch", "NoSchedule")]));
+            reconcile_node(&api, &ctx, name).await;
+            api.delete_node(name);
+        }
+        let third_node = Arc::new(api.node(third).unwrap());
+        let fourth_node = Arc::new(api.node(fourth).unwrap());
+        let (a, b) = tokio::join!(
+            reconcile(third_node, ctx.clone()),
+            reconcile(fourth_node, ctx.clone())
+        );
+        a.unwrap();
+        b.unwrap();
+        assert_eq!(shards(&api)[0].data.as_ref().unwrap().len(), 4);
+    }
+
+    /// Test 3: Per-node records are found before and after they are migrated
+    #[tokio::test]
+    async fn test_migration_to_shards() {
+        let api = FakeApi::new();
+        let per_node = Arc::new(Context::new(api.client(), Config::default()));
+        for name in ["worker-1", "worker-2", "worker-3"] {
+            store_node(
+                &api,
+                &per_node,
+                &node(name, &[("dedicated", "gpu", "NoSchedule")]),
+            )
+            .await;
+        }
+
+        // Switching the layout keeps per-node records readable
+        let ctx = Arc::new(Context::new(api.client(), sharded_config()));
+        assert_eq!(restore_node(&api, &ctx, "worker-1").await, ["dedicated"]);
+
+        // A node deleted before the migration gets a newer shard entry
+        api.add_node(&node("worker-2", &[("team", "a", "NoExecute")]));
+        reconcile_node(&api, &ctx, "worker-2").await;
+        api.delete_node("worker-2");
+        reconcile_node(&api, &ctx, "worker-2").await;
+
+        let mut migrated = migrate_records(&ctx).await.unwrap();
+        migrated.sort();
+        assert_eq!(migrated, ["worker-1", "worker-3"]);
+        assert!(api
+            .configmaps("default")
+            .iter()
+            .all(|cm| cm.labels().contains_key(SHARD_LABEL)));
+        assert!(migrate_records(&ctx).await.unwrap().is_empty());
+
+        assert_eq!(restore_node(&api, &ctx, "worker-2").await, ["team"]);
+        assert_eq!(restore_node(&api, &ctx, "worker-3").await, ["dedicated"]);
+    }
+
+    /// Test 4: Records follow changes of the shard prefix length and the
+    /// switch back to per-node records
+    #[tokio::test]
+    async fn test_migration_across_layouts() {
+        let api = FakeApi::new();
+        let short = Arc::new(Context::new(api.client(), sharded_config()));
+        for name in ["worker-1", "worker-2", "worker-3"] {
+            store_node(
+                &api,
+                &short,
+                &node(name, &[("dedicated", "gpu", "NoSchedule")]),
+            )
+            .await;
+        }
+
+        // Entries of shards with the old prefix length stay readable
+        let long = Arc::new(Context::new(
+            api.client(),
+            Config {
+                shard_prefix_length: 2,
+                ..sharded_config()
+            },
+        ));
+        assert_eq!(restore_node(&api, &long, "worker-1").await, ["dedicated"]);
+
+        let mut migrated = migrate_records(&long).await.unwrap();
+        migrated.sort();
+        assert_eq!(migrated, ["worker-1", "worker-2", "worker-3"]);
+        assert!(shards(&api)
+            .iter()
+            .all(|shard| shard.labels()[SHARD_LABEL].len() == 2));
+        assert!(migrate_records(&long).await.unwrap().is_empty());
+        assert_eq!(restore_node(&api, &long, "worker-2").await, ["dedicated"]);
+
+        // Switching back to per-node records moves the entries out of the
+        // shards, keeping records written since the switch
+        let per_node = Arc::new(Context::new(api.client(), Config::default()));
+        api.delete_node("worker-1");
+        reconcile_node(&api, &per_node, "worker-1").await;
+        let mut migrated = migrate_records(&per_node).await.unwrap();
+        migrated.sort();
+        assert_eq!(migrated, ["worker-1", "worker-2", "worker-3"]);
+        assert!(shards(&api).is_empty());
+        assert_eq!(api.configmaps("default").len(), 3);
+        assert_eq!(
+            restore_node(&api, &per_node, "worker-3").await,
+            ["dedicated"]
+        );
+    }
+
+    /// Test 5: Records that would overflow their shard fail the cleanup
+    #[tokio::test]
+    async fn test_full_shard_rejected() {
+        let api = FakeApi::new();
+        let ctx = Arc::new(Context::new(api.client(), sharded_config()));
+        let value = "x".repeat(60 * 1024);
+        let names: Vec<String> = (0..)
+            .map(|i| format!("worker-{}", i))
+            .filter(|name| shard_prefix(name) == shard_prefix("worker-0"))
+            .take(13)
+            .collect();
+        for name in &names[..12] {
+            store_node(
+                &api,
+                &ctx,
+                &node(name, &[("dedicated", &value, "NoSchedule")]),
+            )
+            .await;
+        }
+
+        let last = &names[12];
+        api.add_node(&node(last, &[("dedicated", &value, "NoSchedule")]));
+        reconcile_node(&api, &ctx, last).await;
+        api.delete_node(last);
+        let node = Arc::new(api.node(last).unwrap());
+        let error = reconcile(node, ctx).await.unwrap_err();
+        assert!(
+            matches!(error, Error::Finalizer(ref e) if e.contains("full")),
+            "{}",
+            error
+        );
+        assert!(api.node(last).is_some(), "finalizer should be kept");
+    }
+
+    /// Test 6: Large per-node records are gzipped into binary data and restored
+    #[tokio::test]
+    async fn test_large_record_compressed() {
+        let api = FakeApi::new();
+        let ctx = Arc::new(Context::new(api.client(), Config::default()));
+        let value = "x".repeat(1024);
+        let taints: Vec<(String, &str, &str)> = (0..200)
+            .map(|i| (format!("team-{}", i), value.as_str(), "NoSchedule"))
+            .collect();
+        let taints: Vec<(&str, &str, &str)> = taints
+            .iter()
+            .map(|(k, v, e)| (k.as_str(), *v, *e))
+            .collect();
+        store_node(&api, &ctx, &node("worker-1", &taints)).await;
+
+        let cm = &api.configmaps("default")[0];
+        assert!(!cm
+            .data
+            .as_ref()
+            .unwrap()
+            .contains_key("preserved_taints_json"));
+        assert!(cm
+            .binary_data
+            .as_ref()
+            .unwrap()
+            .contains_key("preserved_taints_json.gz"));
+        assert_eq!(restore_node(&api, &ctx, "worker-1").await.len(), 200);
+
+        // Small records are written as plain JSON again
+        store_node(
+            &api,
+            &ctx,
+            &node("worker-1", &[("dedicated", "gpu", "NoSchedule")]),
+        )
+        .await;
+        let cm = &api.configmaps("default")[0];
+        assert!(!cm
+            .binary_data
+            .as_ref()
+            .unwrap()
+            .contains_key("preserved_taints_json.gz"));
+        assert_eq!(restore_node(&api, &ctx, "worker-1").await, ["dedicated"]);
+    }
+
+    /// Test 7: Records too large even when compressed keep their first
+    /// taints and are reported, instead of failing the cleanup
+    #[tokio::test]
+    async fn test_oversized_record_truncated() {
+        let api = FakeApi::new();
+        let ctx = Arc::new(Context::new(api.client(), Config::default()));
+        // Hashes compress poorly, so five of these exceed the limit
+        let values: Vec<String> = (0..5)
+            .map(|i| {
+                (0..6000)
+                    .map(|j| hex::encode(Sha256::digest(format!("{}-{}", i, j))))
+                    .collect()
+            })
+            .collect();
+        let keys: Vec<String> = (0..5).map(|i| format!("team-{}", i)).collect();
+        let taints: Vec<(&str, &str, &str)> = keys
+            .iter()
+            .zip(&values)
+            .map(|(k, v)| (k.as_str(), v.as_str(), "NoSchedule"))
+            .collect();
+        store_node(&api, &ctx, &node("worker-1", &taints)).await;
+
+        let restored = restore_node(&api, &ctx, "worker-1").await;
+        assert!(!restored.is_empty() && restored.len() < 5, "{:?}", restored);
+        assert_eq!(restored, keys[..restored.len()]);
+        assert!(api
+            .events()
+            .iter()
+            .any(|e| e.reason.as_deref() == Some("RecordTruncated")));
+    }
+
+    /// Whether any record ConfigMap contains a string
+    fn records_contain(api: &FakeApi, needle: &str) -> bool {
+        api.configmaps("default")
+            .iter()
+            .any(|cm| serde_json::to_string(cm).unwrap().contains(needle))
+    }
+
+    /// Write a base64-encoded key file
+    fn key_file(name: &str, key: [u8; 32]) -> PathBuf {
+        use base64::Engine;
+        let dir = std::env::temp_dir().join(format!("storage-tests-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        std::fs::write(
+            &path,
+            base64::engine::general_purpose::STANDARD.encode(key) + "\n",
+        )
+        .unwrap();
+        path
+    }
+
+    /// Test 8: Encrypted records hide taint values in both layouts, and
+    /// records written before encryption was enabled are re-encrypted
+    #[tokio::test]
+    async fn test_encrypted_records() {
+        for config in [Config::default(), sharded_config()] {
+            let api = FakeApi::new();
+            let plain = Arc::new(Context::new(api.client(), config.clone()));
+            store_node(
+                &api,
+                &plain,
+                &node("worker-1", &[("tenant", "customer-a", "NoSchedule")]),
+            )
+            .await;
+            assert!(records_contain(&api, "customer-a"));
+
+            let ctx = Arc::new(
+                Context::new(api.client(), config.clone())
+                    .with_keyring(Keyring::from_keys(&[[1; 32]])),
+            );
+            store_node(
+                &api,
+                &ctx,
+                &node("worker-2", &[("tenant", "customer-b", "NoSchedule")]),
+            )
+            .await;
+            assert!(!records_contain(&api, "customer-b"));
+
+            assert_eq!(reseal_records(&ctx).await.unwrap(), ["worker-1"]);
+            assert!(!records_contain(&api, "customer-a"));
+            assert!(reseal_records(&ctx).await.unwrap().is_empty());
+
+            assert_eq!(restore_node(&api, &ctx, "worker-1").await, ["tenant"]);
+            assert_eq!(restore_node(&api, &ctx, "worker-2").await, ["tenant"]);
+        }
+    }
+
+    /// Test 9: Records stay readable while the key is rotated, and need the
+    /// new key only once re-encrypted
+    #[tokio::test]
+    async fn test_key_rotation() {
+        let api = FakeApi::new();
+        let old_key = key_file("old", [1; 32]);
+        let new_key = key_file("new", [2; 32]);
+        let with_keys = |files: Vec<PathBuf>| {
+            let keyring = Keyring::load(&files).unwrap();
+            Arc::new(Context::new(api.client(), Config::default()).with_keyring(keyring))
+        };
+
+        let old = with_keys(vec![old_key.clone()]);
+        for name in ["worker-1", "worker-2"] {
+            store_node(
+                &api,
+                &old,
+                &node(name, &[("tenant", "customer-a", "NoSchedule")]),
+            )
+            .await;
+        }
+
+        // Only the new key cannot read the old records
+        let new_only = with_keys(vec![new_key.clone()]);
+        api.add_node(&node("worker-1", &[]));
+        reconcile_node(&api, &new_only, "worker-1").await;
+        let node_1 = Arc::new(api.node("worker-1").unwrap());
+        let error = reconcile(node_1, new_only.clone()).await.unwrap_err();
+        assert!(
+            matches!(error, Error::Finalizer(ref e) if e.contains("no configured key")),
+            "{}",
+            error
+        );
+        api.force_delete_node("worker-1");
+
+        let rotating = with_keys(vec![new_key, old_key.clone()]);
+        assert_eq!(restore_node(&api, &rotating, "worker-1").await, ["tenant"]);
+        let mut resealed = reseal_records(&rotating).await.unwrap();
+        resealed.sort();
+        assert_eq!(resealed, ["worker-1", "worker-2"]);
+
+        assert_eq!(restore_node(&api, &new_only, "worker-2").await, ["tenant"]);
+
+        let invalid = old_key.with_file_name("invalid");
+        std::fs::write(&invalid, "not-a-key").unwrap();
+        assert!(Keyring::load(&[invalid]).is_err());
+    }
+
+    /// Test 10: Re-encryption writes records with their resourceVersion, and
+    /// re-reads records changed since they were read
+    #[tokio::test]
+    async fn test_reseal_conflict_retried() {
+        let api = FakeApi::new();
+        let plain = Arc::new(Context::new(api.client(), Config::default()));
+        store_node(
+            &api,
+            &plain,
+            &node("worker-1", &[("tenant", "customer-a", "NoSchedule")]),
+        )
+        .await;
+
+        let keyring = Keyring::load(&[key_file("reseal", [3; 32])]).unwrap();
+        let ctx = Arc::new(Context::new(api.client(), Config::default()).with_keyring(keyring));
+        api.fail(Method::PATCH, "configmaps", 409);
+        assert_eq!(reseal_records(&ctx).await.unwrap(), ["worker-1"]);
+        let record_requests = |method: Method| {
+            api.requests()
+                .iter()
+                .filter(|(m, path)| *m == method && path.contains("/configmaps/node-taints-"))
+                .count()
+        };
+        assert_eq!(
+            record_requests(Method::PATCH),
+            3,
+            "cleanup and two attempts"
+        );
+        assert!(record_requests(Method::GET) >= 2);
+        assert!(!records_contain(&api, "customer-a"));
+        assert_eq!(restore_node(&api, &ctx, "worker-1").await, ["tenant"]);
+    }
+
+    /// Test 11: Records without taints are only re-encrypted when their
+    /// history or expiry times are not encrypted with the current key
+    #[tokio::test]
+    async fn test_reseal_records_without_taints() {
+        let api = FakeApi::new();
+        let old_key = key_file("history-old", [4; 32]);
+        let new_key = key_file("history-new", [5; 32]);
+        let with_keys = |files: Vec<PathBuf>| {
+            let keyring = Keyring::load(&files).unwrap();
+            Arc::new(Context::new(api.client(), Config::default()).with_keyring(keyring))
+        };
+
+        let old = with_keys(vec![old_key.clone()]);
+        store_node(
+            &api,
+            &old,
+            &node("worker-1", &[("tenant", "customer-a", "NoSchedule")]),
+        )
+        .await;
+        // The taints were removed, leaving them in the history only
+        store_node(&api, &old, &node("worker-1", &[])).await;
+        assert!(reseal_records(&old).await.unwrap().is_empty());
+
+        let rotating = with_keys(vec![new_key, old_key]);
+        assert_eq!(reseal_records(&rotating).await.unwrap(), ["worker-1"]);
+        assert!(reseal_records(&rotating).await.unwrap().is_empty());
+        assert!(!records_contain(&api, "customer-a"));
+    }
+}
@@ -1,11 +1,12 @@
 #[cfg(test)]
 mod tests {
-    use k8s_openapi::api::core::v1::{Node, NodeSpec, Taint};
+    use k8s_openapi::api::core::v1::{ConfigMap, Node, NodeSpec, Taint};
     use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
-    use kube::api::{Api, DeleteParams, PostParams};
+    use kube::api::{Api, DeleteParams, Patch, PatchParams, PostParams};
     use kube::Client;
     use rand::{distr::Alphanumeric, rng, Rng};
     use serde_json::json;
+    use sha2::{Digest, Sha256};
 
     /// Generate a random node name
     fn random_node_name(length: usize) -> String {
@@ -64,11 +65,7 @@ mod tests {
         });
 
         nodes
-            .patch(
-                node_name,
-                &kube::api::PatchParams::default(),
-                &kube::api::Patch::Merge(patch),
-            )
+            .patch(node_name, &PatchParams::default(), &Patch::Merge(patch))
             .await?;
         Ok(())
     }
@@ -439,4 +436,138 @@ mod tests {
         // Cleanup
         delete_node(&client, &node_name).await.ok();
     }
+
+    /// Test 7: Opted-out nodes are not managed
+    #[tokio::test]
+    async fn test_opt_out_node() {
+        let client = Client::try_default().await.unwrap();
+        let node_name = format!("test-optout-{}", random_node_name(10));
+
+        // Create node with the skip label
+        let nodes: Api<Node> = Api::all(client.clone());
+        let node = Node {
+            metadata: ObjectMeta {
+                name: Some(node_name.clone()),
+                labels: Some(
+                    [(
+                        "nodetaintpreserver.example.com/skip".to_string(),
+                        "true".to_string(),
+                    )]
+                    .into(),
+                ),
+                ..Default::default()
+            },
+            spec: Some(NodeSpec::default()),
+            ..Default::default()
+        };
+        nodes.create(&PostParams::default(), &node).await.unwrap();
+        wait_for_node(&client, &node_name, true).await.unwrap();
+
+        // Add custom taint
+        let taint = Taint {
+            key: "custom.example.com/optout".to_string(),
+            value: Some("value".to_string()),
+            effect: "NoSchedule".to_string(),
+            ..Default::default()
+        };
+        set_node_taints(&client, &node_name, vec![taint.clone()])
+            .await
+            .unwrap();
+
+        // Give controller time to reconcile
+        tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+
+        // Verify the finalizer was never added
+        let node = nodes.get(&node_name).await.unwrap();
+        let has_finalizer = node
+            .metadata
+            .finalizers
+            .as_ref()
+            .map(|f| {
+                f.iter()
+                    .any(|f| f == "nodetaintpreserver.example.com/finalizer")
+            })
+            .unwrap_or(false);
+        assert!(!has_finalizer, "Opted-out node should not get a finalizer");
+
+        // Delete and recreate without the skip label
+        delete_node(&client, &node_name).await.unwrap();
+        create_node(&client, &node_name).await.unwrap();
+
+        // Give controller time to reconcile
+        tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+
+        // Verify taint is NOT restored, as no record was written
+        let node = nodes.get(&node_name).await.unwrap();
+        let has_taint = node
+            .spec
+            .as_ref()
+            .and_then(|spec| spec.taints.as_ref())
+            .map(|taints| taints.iter().any(|t| t.key == taint.key))
+            .unwrap_or(false);
+        assert!(
+            !has_taint,
+            "Taint of an opted-out node should not be restored"
+        );
+
+        // Cleanup
+        delete_node(&client, &node_name).await.ok();
+    }
+
+    /// Test 8: Record edits are restored onto live nodes
+    #[tokio::test]
+    async fn test_record_edit_restored() {
+        let client = Client::try_default().await.unwrap();
+        let node_name = format!("test-recordedit-{}", random_node_name(10));
+
+        // Create node and wait for the initial restore pass
+        create_node(&client, &node_name).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+
+        // Write a record for the live node
+        let taint = Taint {
+            key: "custom.example.com/recordedit".to_string(),
+            value: Some("value".to_string()),
+            effect: "NoSchedule".to_string(),
+            ..Default::default()
+        };
+        // Records are watched by their labels
+        let node_hash = hex::encode(Sha256::digest(node_name.as_bytes()));
+        let cm_name = format!("node-taints-{}", node_hash);
+        let cm = json!({
+            "apiVersion": "v1",
+            "kind": "ConfigMap",
+            "metadata": {
+                "name": cm_name,
+                "labels": {
+                    "app.kubernetes.io/managed-by": "node-taint-preserver",
+                    "app.kubernetes.io/component": "taint-record",
+                    // Label values are limited to 63 characters
+                    "nodetaintpreserver.example.com/node-hash": &node_hash[..63]
+                },
+                "annotations": {
+                    "nodetaintpreserver.example.com/node-name": node_name
+                }
+            },
+            "data": {
+                "preserved_taints_json": serde_json::to_string(&vec![taint.clone()]).unwrap()
+            }
+        });
+        let cms: Api<ConfigMap> = Api::namespaced(client.clone(), "default");
+        cms.patch(
+            &cm_name,
+            &PatchParams::apply("taints-tests").force(),
+            &Patch::Apply(&cm),
+        )
+        .await
+        .unwrap();
+
+        // Verify taint is restored without recreating the node
+        wait_for_taint(&client, &node_name, &taint.key, true)
+            .await
+            .unwrap();
+
+        // Cleanup
+        delete_node(&client, &node_name).await.ok();
+    }
 }
@@ -0,0 +1,128 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use super::common::{node, reconcile_node, store_node, sync_record_cache, FakeApi};
+    use axum::{body::Bytes, http::StatusCode, routing::post, Router};
+    use kube::ResourceExt;
+    use node_taint_preserver::{telemetry, Config, Context};
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::prelude::*;
+
+    /// Start a stand-in OTLP/HTTP collector recording the request bodies
+    async fn start_collector() -> (String, Arc<Mutex<Vec<Bytes>>>) {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let app = Router::new().route(
+            "/v1/traces",
+            post({
+                let received = received.clone();
+                move |body: Bytes| async move {
+                    received.lock().unwrap().push(body);
+                    StatusCode::OK
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (endpoint, received)
+    }
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack.windows(needle.len()).any(|w| w == needle)
+    }
+
+    /// Test 1: Spans are exported over OTLP with their trace ID and fields
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_spans_exported() {
+        let (endpoint, received) = start_collector().await;
+        // The exporter blocks on HTTP requests, so it must run off the runtime
+        let provider = tokio::task::spawn_blocking(move || {
+            telemetry::tracer_provider(Some(&endpoint)).unwrap()
+        })
+        .await
+        .unwrap();
+
+        let subscriber = tracing_subscriber::registry().with(telemetry::layer(&provider));
+        let trace_id = tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("reconcile", node = "test-node");
+            let _entered = span.enter();
+            telemetry::trace_id(&span)
+        })
+        .expect("span should be part of a valid trace");
+
+        tokio::task::spawn_blocking(move || provider.shutdown().unwrap())
+            .await
+            .unwrap();
+
+        let trace_id = hex::decode(trace_id).unwrap();
+        let received = received.lock().unwrap();
+        assert!(received.iter().any(|body| {
+            contains(body, &trace_id)
+                && contains(body, b"reconcile")
+                && contains(body, b"test-node")
+        }));
+    }
+
+    /// Test 2: Spans outside of a trace have no trace ID
+    #[test]
+    fn test_no_trace_id_without_layer() {
+        let span = tracing::info_span!("reconcile");
+        assert_eq!(telemetry::trace_id(&span), None);
+    }
+
+    /// Test 3: Reconcile spans carry the node, its UID and the outcome, and
+    /// Events emitted during a reconcile note its trace ID
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_reconcile_traced() {
+        let (endpoint, received) = start_collector().await;
+        let provider = tokio::task::spawn_blocking(move || {
+            telemetry::tracer_provider(Some(&endpoint)).unwrap()
+        })
+        .await
+        .unwrap();
+
+        let api = FakeApi::new();
+        let ctx = Arc::new(Context::new(api.client(), Config::default()));
+        store_node(
+            &api,
+            &ctx,
+            &node("worker-1", &[("dedicated", "gpu", "NoSchedule")]),
+        )
+        .await;
+        sync_record_cache(&ctx).await;
+        api.add_node(&node("worker-1", &[]));
+        let uid = api.node("worker-1").unwrap().uid().unwrap();
+
+        let subscriber = tracing_subscriber::registry().with(telemetry::layer(&provider));
+        let guard = tracing::subscriber::set_default(subscriber);
+        reconcile_node(&api, &ctx, "worker-1").await;
+        reconcile_node(&api, &ctx, "worker-1").await;
+        drop(guard);
+
+        tokio::task::spawn_blocking(move || provider.shutdown().unwrap())
+            .await
+            .unwrap();
+
+        let note = api
+            .events()
+            .into_iter()
+            .find(|e| e.reason.as_deref() == Some("TaintsRestored"))
+            .and_then(|e| e.note)
+            .unwrap();
+        let (_, trace_id) = note.rsplit_once("(trace_id=").expect(&note);
+        let trace_id = hex::decode(trace_id.trim_end_matches(')')).unwrap();
+        let exported = received.lock().unwrap().concat();
+        assert!(contains(&exported, &trace_id));
+        for expected in [
+            "reconcile",
+            "apply_node",
+            "worker-1",
+            &uid,
+            "outcome",
+            "success",
+        ] {
+            assert!(contains(&exported, expected.as_bytes()), "{}", expected);
+        }
+    }
+}
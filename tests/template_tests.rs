@@ -0,0 +1,175 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use super::common::{node, FakeApi};
+    use k8s_openapi::api::core::v1::Node;
+    use kube::ResourceExt;
+    use node_taint_preserver::{
+        reconcile,
+        template::{render, validate, TemplateError},
+        Config, Context, RestoreTrigger,
+    };
+    use std::{collections::BTreeMap, sync::Arc};
+
+    const RESTORED_ANNOTATION: &str = "nodetaintpreserver.example.com/taints-restored";
+    const ZONE_LABEL: &str = "topology.kubernetes.io/zone";
+    const GATE_KEY: &str = "nodetaintpreserver.example.com/restore-pending";
+
+    fn labeled(name: &str, labels: &[(&str, &str)]) -> Node {
+        let mut node = node(name, &[]);
+        node.metadata.labels = Some(
+            labels
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        );
+        node
+    }
+
+    async fn reconcile_node(api: &FakeApi, ctx: &Arc<Context>, name: &str) {
+        let node = api.node(name).expect("node should exist");
+        reconcile(Arc::new(node), ctx.clone()).await.unwrap();
+    }
+
+    fn taint_value(api: &FakeApi, name: &str, key: &str) -> Option<String> {
+        let node = api.node(name).unwrap();
+        node.spec?
+            .taints?
+            .into_iter()
+            .find(|t| t.key == key)
+            .and_then(|t| t.value)
+    }
+
+    /// Test 1: Templates render node variables and reject invalid input
+    #[test]
+    fn test_render_template() {
+        let mut node = labeled("worker-1", &[(ZONE_LABEL, "eu-west-1a")]);
+        node.metadata.annotations = Some(BTreeMap::from([(
+            "example.com/pool".to_string(),
+            "batch".to_string(),
+        )]));
+
+        assert_eq!(
+            render("{label:topology.kubernetes.io/zone}-batch", &node).unwrap(),
+            "eu-west-1a-batch"
+        );
+        assert_eq!(
+            render("{ name }.{annotation:example.com/pool}", &node).unwrap(),
+            "worker-1.batch"
+        );
+        assert_eq!(
+            render("{label:missing}", &node),
+            Err(TemplateError::MissingLabel("missing".to_string()))
+        );
+        assert!(matches!(
+            render("{{literal}}", &node),
+            Err(TemplateError::InvalidValue(_))
+        ));
+        assert_eq!(
+            validate("{env:HOME}"),
+            Err(TemplateError::UnknownVariable("env:HOME".to_string()))
+        );
+        assert_eq!(validate("a-{name"), Err(TemplateError::Unterminated(2)));
+        assert_eq!(validate("a}"), Err(TemplateError::UnmatchedBrace(1)));
+
+        let config = Config {
+            taint_templates: BTreeMap::from([("dedicated".to_string(), "{name".to_string())]),
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+    }
+
+    /// Test 2: Templated taints follow the node's current labels, and
+    /// failed templates are reported and retried
+    #[tokio::test]
+    async fn test_restore_templated_taint() {
+        let api = FakeApi::new();
+        let config = Config {
+            taint_templates: BTreeMap::from([(
+                "dedicated".to_string(),
+                "{label:topology.kubernetes.io/zone}-batch".to_string(),
+            )]),
+            ..Default::default()
+        };
+        let ctx = Arc::new(Context::new(api.client(), config));
+
+        let mut old = labeled("worker-1", &[(ZONE_LABEL, "zone-a")]);
+        old.spec = node("worker-1", &[("dedicated", "zone-a-batch", "NoSchedule")]).spec;
+        api.add_node(&old);
+        reconcile_node(&api, &ctx, "worker-1").await;
+        api.delete_node("worker-1");
+        reconcile_node(&api, &ctx, "worker-1").await;
+
+        // Without the label the taint is not restored, nor the record
+        // marked restored
+        api.add_node(&labeled("worker-1", &[]));
+        reconcile_node(&api, &ctx, "worker-1").await;
+        reconcile_node(&api, &ctx, "worker-1").await;
+        assert_eq!(taint_value(&api, "worker-1", "dedicated"), None);
+        assert!(!api
+            .node("worker-1")
+            .unwrap()
+            .annotations()
+            .contains_key(RESTORED_ANNOTATION));
+        assert!(api
+            .events()
+            .iter()
+            .any(|e| e.reason.as_deref() == Some("TaintTemplateFailed")));
+
+        // The node returned in another zone
+        let mut relabeled = api.node("worker-1").unwrap();
+        relabeled
+            .labels_mut()
+            .insert(ZONE_LABEL.to_string(), "zone-b".to_string());
+        api.add_node(&relabeled);
+        reconcile_node(&api, &ctx, "worker-1").await;
+        assert_eq!(
+            taint_value(&api, "worker-1", "dedicated").as_deref(),
+            Some("zone-b-batch")
+        );
+        assert!(api
+            .node("worker-1")
+            .unwrap()
+            .annotations()
+            .contains_key(RESTORED_ANNOTATION));
+    }
+
+    /// Test 3: Gated nodes keep the restore gate while a template fails, and
+    /// are released once every taint was restored
+    #[tokio::test]
+    async fn test_gate_kept_on_template_failure() {
+        let api = FakeApi::new();
+        let config = Config {
+            restore_trigger: RestoreTrigger::StartupTaint,
+            taint_templates: BTreeMap::from([(
+                "dedicated".to_string(),
+                "{label:topology.kubernetes.io/zone}-batch".to_string(),
+            )]),
+            ..Default::default()
+        };
+        let ctx = Arc::new(Context::new(api.client(), config));
+        let mut old = labeled("worker-1", &[(ZONE_LABEL, "zone-a")]);
+        old.spec = node("worker-1", &[("dedicated", "zone-a-batch", "NoSchedule")]).spec;
+        api.add_node(&old);
+        reconcile_node(&api, &ctx, "worker-1").await;
+        api.delete_node("worker-1");
+        reconcile_node(&api, &ctx, "worker-1").await;
+
+        let mut gated = labeled("worker-1", &[]);
+        gated.spec = node("worker-1", &[(GATE_KEY, "", "NoSchedule")]).spec;
+        api.add_node(&gated);
+        reconcile_node(&api, &ctx, "worker-1").await;
+        reconcile_node(&api, &ctx, "worker-1").await;
+        assert_eq!(api.node_taint_keys("worker-1"), [GATE_KEY]);
+        assert!(api.has_event("TaintTemplateFailed"));
+
+        let mut relabeled = api.node("worker-1").unwrap();
+        relabeled
+            .labels_mut()
+            .insert(ZONE_LABEL.to_string(), "zone-b".to_string());
+        api.add_node(&relabeled);
+        reconcile_node(&api, &ctx, "worker-1").await;
+        assert_eq!(api.node_taint_keys("worker-1"), ["dedicated"]);
+    }
+}
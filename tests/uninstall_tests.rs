@@ -0,0 +1,77 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use super::common::{node, reconcile_node, store_node, FakeApi};
+    use http::Method;
+    use kube::ResourceExt;
+    use node_taint_preserver::{uninstall::uninstall, Config, Context};
+    use std::sync::Arc;
+
+    const FINALIZER: &str = "nodetaintpreserver.example.com/finalizer";
+
+    /// Nodes with our finalizer, and a record of a deleted node
+    async fn setup(names: &[&str]) -> (FakeApi, Arc<Context>) {
+        let api = FakeApi::new();
+        let ctx = Arc::new(Context::new(api.client(), Config::default()));
+        store_node(
+            &api,
+            &ctx,
+            &node("worker-0", &[("dedicated", "gpu", "NoSchedule")]),
+        )
+        .await;
+        for name in names {
+            api.add_node(&node(name, &[]));
+            reconcile_node(&api, &ctx, name).await;
+        }
+        (api, ctx)
+    }
+
+    fn has_finalizer(api: &FakeApi, name: &str) -> bool {
+        let node = api.node(name).unwrap();
+        node.finalizers().iter().any(|f| f == FINALIZER)
+    }
+
+    /// Test 1: Uninstalling releases every node and deletes the records,
+    /// and a dry run only reports what would change
+    #[tokio::test]
+    async fn test_uninstall_and_dry_run() {
+        let (api, ctx) = setup(&["worker-1", "worker-2"]).await;
+        assert!(has_finalizer(&api, "worker-1"));
+
+        let report = uninstall(&ctx, true, true).await.unwrap();
+        assert_eq!(report.nodes_released, ["worker-1", "worker-2"]);
+        assert_eq!(report.records_deleted, ["worker-0"]);
+        assert!(has_finalizer(&api, "worker-1"), "dry run");
+        assert_eq!(api.configmaps("default").len(), 1, "dry run");
+
+        let report = uninstall(&ctx, true, false).await.unwrap();
+        assert_eq!(report.nodes_released, ["worker-1", "worker-2"]);
+        assert!(report.nodes_failed.is_empty());
+        assert!(!has_finalizer(&api, "worker-1"));
+        assert!(!has_finalizer(&api, "worker-2"));
+        assert!(api.configmaps("default").is_empty());
+
+        let report = uninstall(&ctx, true, false).await.unwrap();
+        assert!(report.nodes_released.is_empty());
+    }
+
+    /// Test 2: Nodes that changed concurrently are re-read and released, and
+    /// a node that fails to be released is reported without stopping the rest
+    #[tokio::test]
+    async fn test_uninstall_conflicts_and_failures() {
+        let (api, ctx) = setup(&["worker-1", "worker-2", "worker-3"]).await;
+        api.fail_object(Method::PATCH, "nodes", "worker-1", 409);
+        api.fail_object(Method::PATCH, "nodes", "worker-2", 500);
+
+        let report = uninstall(&ctx, false, false).await.unwrap();
+        assert_eq!(report.nodes_released, ["worker-1", "worker-3"]);
+        assert_eq!(report.nodes_failed.keys().collect::<Vec<_>>(), ["worker-2"]);
+        assert!(api
+            .requests()
+            .contains(&(Method::GET, "api/v1/nodes/worker-1".to_string())));
+        assert!(!has_finalizer(&api, "worker-1"));
+        assert!(has_finalizer(&api, "worker-2"));
+        assert!(!has_finalizer(&api, "worker-3"));
+    }
+}